@@ -0,0 +1,10 @@
+ALTER TABLE pairs ALTER CONSTRAINT token0_data NOT DEFERRABLE;
+ALTER TABLE pairs ALTER CONSTRAINT token1_data NOT DEFERRABLE;
+ALTER TABLE pair_hour_data ALTER CONSTRAINT pair_data NOT DEFERRABLE;
+ALTER TABLE pair_day_data ALTER CONSTRAINT token0_data NOT DEFERRABLE;
+ALTER TABLE pair_day_data ALTER CONSTRAINT token1_data NOT DEFERRABLE;
+ALTER TABLE pair_day_data ALTER CONSTRAINT pair_data NOT DEFERRABLE;
+ALTER TABLE token_day_data ALTER CONSTRAINT token_data NOT DEFERRABLE;
+
+DROP TABLE journal;
+DROP TABLE blocks;
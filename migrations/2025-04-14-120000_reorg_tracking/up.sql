@@ -0,0 +1,25 @@
+CREATE TABLE blocks (
+    number INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL,
+    parent_hash TEXT NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+
+CREATE TABLE journal (
+    id SERIAL PRIMARY KEY,
+    first_block INTEGER NOT NULL,
+    last_block INTEGER NOT NULL,
+    entity TEXT NOT NULL,
+    entity_id TEXT NOT NULL,
+    data JSONB
+);
+
+CREATE INDEX journal_last_block ON journal (last_block);
+
+ALTER TABLE pairs ALTER CONSTRAINT token0_data DEFERRABLE;
+ALTER TABLE pairs ALTER CONSTRAINT token1_data DEFERRABLE;
+ALTER TABLE pair_hour_data ALTER CONSTRAINT pair_data DEFERRABLE;
+ALTER TABLE pair_day_data ALTER CONSTRAINT token0_data DEFERRABLE;
+ALTER TABLE pair_day_data ALTER CONSTRAINT token1_data DEFERRABLE;
+ALTER TABLE pair_day_data ALTER CONSTRAINT pair_data DEFERRABLE;
+ALTER TABLE token_day_data ALTER CONSTRAINT token_data DEFERRABLE;
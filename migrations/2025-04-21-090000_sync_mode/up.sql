@@ -0,0 +1 @@
+ALTER TABLE sync_state ADD COLUMN mode TEXT NOT NULL DEFAULT 'latest';
@@ -0,0 +1,2 @@
+ALTER TABLE tokens DROP COLUMN price_route_liquidity_eth;
+ALTER TABLE tokens DROP COLUMN price_route;
@@ -0,0 +1,2 @@
+ALTER TABLE tokens ADD COLUMN price_route TEXT NOT NULL DEFAULT '';
+ALTER TABLE tokens ADD COLUMN price_route_liquidity_eth NUMERIC NOT NULL DEFAULT 0;
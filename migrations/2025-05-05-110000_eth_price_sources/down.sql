@@ -0,0 +1 @@
+ALTER TABLE bundles DROP COLUMN eth_price_sources;
@@ -0,0 +1 @@
+ALTER TABLE bundles ADD COLUMN eth_price_sources JSONB NOT NULL DEFAULT '[]';
@@ -0,0 +1,7 @@
+CREATE TABLE eth_price_history (
+    block_number INTEGER PRIMARY KEY,
+    timestamp INTEGER NOT NULL,
+    eth_price NUMERIC NOT NULL
+);
+
+CREATE INDEX eth_price_history_timestamp ON eth_price_history (timestamp);
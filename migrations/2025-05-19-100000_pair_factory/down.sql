@@ -0,0 +1,6 @@
+UPDATE journal SET data = data - 'factory'
+WHERE entity = 'pairs' AND data IS NOT NULL;
+
+DROP INDEX pairs_factory;
+
+ALTER TABLE pairs DROP COLUMN factory;
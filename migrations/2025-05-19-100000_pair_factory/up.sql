@@ -0,0 +1,10 @@
+-- Pairs indexed before several factories were supported belong to the
+-- original factory.
+ALTER TABLE pairs ADD COLUMN factory TEXT NOT NULL DEFAULT 'taya_swap';
+ALTER TABLE pairs ALTER COLUMN factory DROP DEFAULT;
+
+CREATE INDEX pairs_factory ON pairs (factory);
+
+-- Journaled snapshots are restored as whole rows on rollback.
+UPDATE journal SET data = data || '{"factory": "taya_swap"}'
+WHERE entity = 'pairs' AND data IS NOT NULL;
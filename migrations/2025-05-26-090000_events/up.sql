@@ -0,0 +1,14 @@
+CREATE TABLE events (
+    id TEXT PRIMARY KEY,
+    transaction TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    log_index INTEGER NOT NULL,
+    address TEXT NOT NULL,
+    contract TEXT NOT NULL,
+    event TEXT NOT NULL,
+    args JSONB NOT NULL
+);
+
+CREATE INDEX events_block_number ON events (block_number);
+CREATE INDEX events_address_event ON events (address, event);
@@ -1,2 +1,3 @@
+pub mod aggregator;
 pub mod erc20;
 pub mod factory;
@@ -1,23 +1,69 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+
+use alloy::{json_abi::JsonAbi, primitives::Address};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PairSide {
+    Token0,
+    Token1,
+}
+
+#[derive(Debug, Clone)]
+pub struct StablePair {
+    pub pair: &'static str,
+    pub stable_side: PairSide,
+    pub stable_decimals: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Factory {
+    pub name: &'static str,
+    pub address: &'static str,
+}
+
+// A contract whose events are decoded with its JSON ABI and stored in the
+// events table.
+#[derive(Debug, Clone)]
+pub struct Contract {
+    pub name: &'static str,
+    pub address: &'static str,
+    pub abi: &'static str,
+}
 
 #[derive(Debug, Clone)]
 pub struct Chain {
     pub id: u64,
     pub name: &'static str,
-    pub factory: &'static str,
+    pub factories: &'static [Factory],
     pub start_block: i32,
     pub weth: &'static str,
     pub whitelist_tokens: &'static [&'static str],
-    pub usdc_weth_pair: Option<&'static str>,
-    pub usdt_weth_pair: Option<&'static str>,
+    pub stable_pairs: &'static [StablePair],
+    pub minimum_stable_liquidity_eth: i32,
+    pub native_usd_oracle: Option<&'static str>,
+    pub native_usd_oracle_max_age: u64,
     pub minimum_usd_threshold_new_pairs: i32,
     pub minimum_liquidity_threshold_eth: i32,
+    pub min_logs_range: u64,
+    pub initial_logs_range: u64,
+    pub max_logs_range: u64,
+    pub max_filter_addresses: usize,
+    pub contracts: &'static [Contract],
 }
 
+// Seconds an oracle answer stays valid, the heartbeat of most Chainlink
+// feeds.
+pub const DEFAULT_ORACLE_MAX_AGE: u64 = 86_400;
+
 pub const TESTNET: Chain = Chain {
     id: 10143,
     name: "testnet",
-    factory: "0xf3fd5503fb2bb5f5a7ae713e621ac5c50f191fb3",
+    factories: &[Factory {
+        name: "taya_swap",
+        address: "0xf3fd5503fb2bb5f5a7ae713e621ac5c50f191fb3",
+    }],
     start_block: 5253609,
     weth: "0x760afe86e5de5fa0ee542fc7b7b713e1c5425701",
     whitelist_tokens: &[
@@ -25,28 +71,585 @@ pub const TESTNET: Chain = Chain {
         "0xf817257fed379853cde0fa4f97ab987181b1e5ea", // USDC
         "0x88b8e2161dedc77ef4ab7585569d2415a1c1055d", // USDT
     ],
-    usdc_weth_pair: Some("0x1512cb2431b9b14ed14e39dad75496b922481cfd"),
-    usdt_weth_pair: Some("0x488e1d7f4ac40ff42817efbdb5db36508277dc99"),
+    stable_pairs: &[
+        // USDC
+        StablePair {
+            pair: "0x1512cb2431b9b14ed14e39dad75496b922481cfd",
+            stable_side: PairSide::Token1,
+            stable_decimals: 6,
+        },
+        // USDT
+        StablePair {
+            pair: "0x488e1d7f4ac40ff42817efbdb5db36508277dc99",
+            stable_side: PairSide::Token1,
+            stable_decimals: 6,
+        },
+    ],
+    minimum_stable_liquidity_eth: 0,
+    native_usd_oracle: None,
+    native_usd_oracle_max_age: DEFAULT_ORACLE_MAX_AGE,
     minimum_usd_threshold_new_pairs: 10000,
     minimum_liquidity_threshold_eth: 2,
+    min_logs_range: 1,
+    initial_logs_range: 100,
+    max_logs_range: 100,
+    max_filter_addresses: 1000,
+    contracts: &[],
 };
 
 pub static CHAINS: [Chain; 1] = [TESTNET];
 
-pub fn get_chains() -> HashMap<String, Chain> {
+impl Chain {
+    pub fn factory_addresses(&self) -> Vec<Address> {
+        self.factories
+            .iter()
+            .map(|factory| factory.address.parse::<Address>().unwrap())
+            .collect()
+    }
+
+    pub fn factory_by_address(&self, address: &str) -> Option<&Factory> {
+        self.factories.iter().find(|factory| factory.address == address)
+    }
+
+    // Pairs of this factory are used for pricing unless cross-factory
+    // pricing is enabled.
+    pub fn pricing_factory(&self) -> &Factory {
+        &self.factories[0]
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StablePairDefinition {
+    pair: String,
+    stable_side: PairSide,
+    stable_decimals: i32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FactoryDefinition {
+    name: String,
+    address: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ContractDefinition {
+    name: String,
+    address: String,
+    abi: String,
+    #[serde(skip)]
+    abi_json: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChainDefinition {
+    id: u64,
+    name: String,
+    factories: Vec<FactoryDefinition>,
+    start_block: i32,
+    weth: String,
+    whitelist_tokens: Vec<String>,
+    #[serde(default)]
+    stable_pairs: Vec<StablePairDefinition>,
+    #[serde(default)]
+    minimum_stable_liquidity_eth: i32,
+    #[serde(default)]
+    native_usd_oracle: Option<String>,
+    #[serde(default = "default_oracle_max_age")]
+    native_usd_oracle_max_age: u64,
+    minimum_usd_threshold_new_pairs: i32,
+    minimum_liquidity_threshold_eth: i32,
+    min_logs_range: u64,
+    initial_logs_range: u64,
+    max_logs_range: u64,
+    max_filter_addresses: usize,
+    #[serde(default)]
+    contracts: Vec<ContractDefinition>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChainsFile {
+    chains: Vec<ChainDefinition>,
+}
+
+impl ChainDefinition {
+    fn validate(&self) -> Result<(), String> {
+        // The name is also used as the database schema of the chain.
+        if !is_identifier(&self.name) {
+            return Err(
+                "name must only contain lowercase letters, digits and underscores"
+                    .to_owned(),
+            );
+        }
+
+        if self.factories.is_empty() {
+            return Err("no factories defined".to_owned());
+        }
+
+        for (index, factory) in self.factories.iter().enumerate() {
+            if !is_identifier(&factory.name) {
+                return Err(format!(
+                    "invalid factory name {}, it must only contain lowercase letters, digits and underscores",
+                    factory.name
+                ));
+            }
+
+            if self.factories[..index].iter().any(|other| {
+                other.name == factory.name
+                    || other.address.to_lowercase()
+                        == factory.address.to_lowercase()
+            }) {
+                return Err(format!(
+                    "factory {} is defined twice",
+                    factory.name
+                ));
+            }
+        }
+
+        let mut addresses = vec![("weth", &self.weth)];
+
+        addresses.extend(
+            self.factories
+                .iter()
+                .map(|factory| ("factory", &factory.address)),
+        );
+
+        addresses.extend(
+            self.whitelist_tokens
+                .iter()
+                .map(|token| ("whitelist token", token)),
+        );
+
+        addresses.extend(
+            self.stable_pairs
+                .iter()
+                .map(|stable| ("stable pair", &stable.pair)),
+        );
+
+        if let Some(oracle) = &self.native_usd_oracle {
+            addresses.push(("native usd oracle", oracle));
+        }
+
+        addresses.extend(
+            self.contracts
+                .iter()
+                .map(|contract| ("contract", &contract.address)),
+        );
+
+        for (field, address) in addresses {
+            if Address::from_str(address).is_err() {
+                return Err(format!(
+                    "invalid {} address {}",
+                    field, address
+                ));
+            }
+        }
+
+        if self.start_block < 0 {
+            return Err("start_block is negative".to_owned());
+        }
+
+        if self.minimum_stable_liquidity_eth < 0
+            || self.minimum_usd_threshold_new_pairs < 0
+            || self.minimum_liquidity_threshold_eth < 0
+        {
+            return Err("thresholds can not be negative".to_owned());
+        }
+
+        for stable in &self.stable_pairs {
+            if !(0..=77).contains(&stable.stable_decimals) {
+                return Err(format!(
+                    "invalid stable_decimals {} for pair {}",
+                    stable.stable_decimals, stable.pair
+                ));
+            }
+        }
+
+        if self.min_logs_range == 0
+            || self.min_logs_range > self.initial_logs_range
+            || self.initial_logs_range > self.max_logs_range
+        {
+            return Err(
+                "logs ranges must satisfy 0 < min_logs_range <= initial_logs_range <= max_logs_range"
+                    .to_owned(),
+            );
+        }
+
+        if self.max_filter_addresses == 0 {
+            return Err("max_filter_addresses must be positive".to_owned());
+        }
+
+        for (index, contract) in self.contracts.iter().enumerate() {
+            if contract.name.is_empty() {
+                return Err(format!(
+                    "contract {} has no name",
+                    contract.address
+                ));
+            }
+
+            if self.contracts[..index].iter().any(|other| {
+                other.address.to_lowercase()
+                    == contract.address.to_lowercase()
+            }) {
+                return Err(format!(
+                    "contract {} is defined twice",
+                    contract.address
+                ));
+            }
+
+            let abi: JsonAbi = serde_json::from_str(&contract.abi_json)
+                .map_err(|err| {
+                    format!("invalid abi {}: {}", contract.abi, err)
+                })?;
+
+            if abi.events().next().is_none() {
+                return Err(format!(
+                    "abi {} of contract {} has no events",
+                    contract.abi, contract.name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn into_chain(self) -> Chain {
+        let stable_pairs: Vec<StablePair> = self
+            .stable_pairs
+            .into_iter()
+            .map(|stable| StablePair {
+                pair: leak_address(stable.pair),
+                stable_side: stable.stable_side,
+                stable_decimals: stable.stable_decimals,
+            })
+            .collect();
+
+        let factories: Vec<Factory> = self
+            .factories
+            .into_iter()
+            .map(|factory| Factory {
+                name: Box::leak(factory.name.into_boxed_str()),
+                address: leak_address(factory.address),
+            })
+            .collect();
+
+        let whitelist_tokens: Vec<&'static str> =
+            self.whitelist_tokens.into_iter().map(leak_address).collect();
+
+        let contracts: Vec<Contract> = self
+            .contracts
+            .into_iter()
+            .map(|contract| Contract {
+                name: Box::leak(contract.name.into_boxed_str()),
+                address: leak_address(contract.address),
+                abi: Box::leak(contract.abi_json.into_boxed_str()),
+            })
+            .collect();
+
+        Chain {
+            id: self.id,
+            name: Box::leak(self.name.into_boxed_str()),
+            factories: Box::leak(factories.into_boxed_slice()),
+            start_block: self.start_block,
+            weth: leak_address(self.weth),
+            whitelist_tokens: Box::leak(
+                whitelist_tokens.into_boxed_slice(),
+            ),
+            stable_pairs: Box::leak(stable_pairs.into_boxed_slice()),
+            minimum_stable_liquidity_eth: self
+                .minimum_stable_liquidity_eth,
+            native_usd_oracle: self.native_usd_oracle.map(leak_address),
+            native_usd_oracle_max_age: self.native_usd_oracle_max_age,
+            minimum_usd_threshold_new_pairs: self
+                .minimum_usd_threshold_new_pairs,
+            minimum_liquidity_threshold_eth: self
+                .minimum_liquidity_threshold_eth,
+            min_logs_range: self.min_logs_range,
+            initial_logs_range: self.initial_logs_range,
+            max_logs_range: self.max_logs_range,
+            max_filter_addresses: self.max_filter_addresses,
+            contracts: Box::leak(contracts.into_boxed_slice()),
+        }
+    }
+}
+
+fn default_oracle_max_age() -> u64 {
+    DEFAULT_ORACLE_MAX_AGE
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().all(|c| {
+            c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'
+        })
+}
+
+// Chains are loaded once at startup and live for the whole process.
+fn leak_address(address: String) -> &'static str {
+    Box::leak(address.to_lowercase().into_boxed_str())
+}
+
+fn load_chains(path: &str) -> Vec<Chain> {
+    let contents = fs::read_to_string(path)
+        .unwrap_or_else(|err| panic!("unable to read {}: {}", path, err));
+
+    let mut file: ChainsFile = match Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some("json") => {
+            serde_json::from_str(&contents).unwrap_or_else(|err| {
+                panic!("unable to parse {}: {}", path, err)
+            })
+        }
+        _ => toml::from_str(&contents).unwrap_or_else(|err| {
+            panic!("unable to parse {}: {}", path, err)
+        }),
+    };
+
+    // ABI paths are relative to the directory of the file.
+    let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+
+    for contract in file
+        .chains
+        .iter_mut()
+        .flat_map(|definition| definition.contracts.iter_mut())
+    {
+        let abi_path = directory.join(&contract.abi);
+
+        contract.abi_json =
+            fs::read_to_string(&abi_path).unwrap_or_else(|err| {
+                panic!("unable to read {}: {}", abi_path.display(), err)
+            });
+    }
+
+    let mut names = Vec::new();
+
+    for definition in file.chains.iter() {
+        if let Err(err) = definition.validate() {
+            panic!(
+                "invalid chain {} in {}: {}",
+                definition.name, path, err
+            );
+        }
+
+        if names.contains(&definition.name) {
+            panic!(
+                "chain {} is defined twice in {}",
+                definition.name, path
+            );
+        }
+
+        names.push(definition.name.clone());
+    }
+
+    file.chains.into_iter().map(ChainDefinition::into_chain).collect()
+}
+
+pub fn get_chains(chains_config: Option<&str>) -> HashMap<String, Chain> {
     let mut chains: HashMap<String, Chain> = HashMap::new();
 
     for chain in CHAINS.iter() {
         chains.insert(chain.name.to_owned(), chain.to_owned());
     }
 
+    // Chains from the file override the built-in ones with the same name.
+    if let Some(path) = chains_config {
+        for chain in load_chains(path) {
+            chains.insert(chain.name.to_owned(), chain);
+        }
+    }
+
     chains
 }
 
-pub fn get_chain(chain: String) -> Chain {
-    let chains = get_chains();
+pub fn get_chain(chain: String, chains_config: Option<&str>) -> Chain {
+    let chains = get_chains(chains_config);
 
     let selected_chain = chains.get(&chain).expect("chain not found.");
 
     selected_chain.to_owned()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WETH: &str = "0x760afe86e5de5fa0ee542fc7b7b713e1c5425701";
+    const FACTORY: &str = "0x733e88f248b742db6c14c0b1713af5ad7fdd59d0";
+    const PAIR: &str = "0x58fdcb0e1ef0f4bb2f6b1d3f3e8d2c2a6b5e8a11";
+
+    fn valid_definition() -> ChainDefinition {
+        toml::from_str(&format!(
+            r#"
+            id = 10143
+            name = "testnet"
+            start_block = 100
+            weth = "{WETH}"
+            whitelist_tokens = ["{WETH}"]
+            minimum_usd_threshold_new_pairs = 1000
+            minimum_liquidity_threshold_eth = 1
+            min_logs_range = 10
+            initial_logs_range = 100
+            max_logs_range = 1000
+            max_filter_addresses = 500
+
+            [[factories]]
+            name = "uniswap"
+            address = "{FACTORY}"
+
+            [[stable_pairs]]
+            pair = "{PAIR}"
+            stable_side = "token1"
+            stable_decimals = 6
+            "#
+        ))
+        .unwrap()
+    }
+
+    fn contract(address: &str, abi_json: &str) -> ContractDefinition {
+        ContractDefinition {
+            name: "router".to_owned(),
+            address: address.to_owned(),
+            abi: "router.json".to_owned(),
+            abi_json: abi_json.to_owned(),
+        }
+    }
+
+    #[test]
+    fn accepts_a_valid_definition() {
+        let definition = valid_definition();
+
+        assert_eq!(definition.validate(), Ok(()));
+        assert_eq!(
+            definition.native_usd_oracle_max_age,
+            DEFAULT_ORACLE_MAX_AGE
+        );
+    }
+
+    #[test]
+    fn rejects_a_name_that_is_not_a_schema_identifier() {
+        let mut definition = valid_definition();
+        definition.name = "Test-Net".to_owned();
+
+        assert!(definition
+            .validate()
+            .unwrap_err()
+            .starts_with("name must"));
+    }
+
+    #[test]
+    fn rejects_missing_and_duplicated_factories() {
+        let mut definition = valid_definition();
+        definition.factories.clear();
+
+        assert_eq!(
+            definition.validate(),
+            Err("no factories defined".to_owned())
+        );
+
+        let mut definition = valid_definition();
+        definition.factories.push(FactoryDefinition {
+            name: "fork".to_owned(),
+            address: FACTORY.to_uppercase().replace("0X", "0x"),
+        });
+
+        assert_eq!(
+            definition.validate(),
+            Err("factory fork is defined twice".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        let mut definition = valid_definition();
+        definition.whitelist_tokens.push("0x1234".to_owned());
+
+        assert_eq!(
+            definition.validate(),
+            Err("invalid whitelist token address 0x1234".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_negative_values() {
+        let mut definition = valid_definition();
+        definition.start_block = -1;
+
+        assert_eq!(
+            definition.validate(),
+            Err("start_block is negative".to_owned())
+        );
+
+        let mut definition = valid_definition();
+        definition.minimum_liquidity_threshold_eth = -1;
+
+        assert_eq!(
+            definition.validate(),
+            Err("thresholds can not be negative".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_stable_decimals() {
+        let mut definition = valid_definition();
+        definition.stable_pairs[0].stable_decimals = 78;
+
+        assert_eq!(
+            definition.validate(),
+            Err(format!("invalid stable_decimals 78 for pair {}", PAIR))
+        );
+    }
+
+    #[test]
+    fn rejects_unordered_logs_ranges() {
+        let mut definition = valid_definition();
+        definition.initial_logs_range = 5;
+
+        assert!(definition
+            .validate()
+            .unwrap_err()
+            .starts_with("logs ranges must satisfy"));
+
+        let mut definition = valid_definition();
+        definition.min_logs_range = 0;
+
+        assert!(definition
+            .validate()
+            .unwrap_err()
+            .starts_with("logs ranges must satisfy"));
+    }
+
+    #[test]
+    fn rejects_contracts_without_events() {
+        let mut definition = valid_definition();
+        definition.contracts.push(contract(FACTORY, "[]"));
+
+        assert_eq!(
+            definition.validate(),
+            Err("abi router.json of contract router has no events"
+                .to_owned())
+        );
+
+        let mut definition = valid_definition();
+        definition.contracts.push(contract(FACTORY, "{"));
+
+        assert!(definition
+            .validate()
+            .unwrap_err()
+            .starts_with("invalid abi router.json"));
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let result = toml::from_str::<ChainDefinition>(
+            "id = 1\nname = \"test\"\nunknown = 1",
+        );
+
+        assert!(result.is_err());
+    }
+}
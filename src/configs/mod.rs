@@ -1,22 +1,86 @@
+use std::num::NonZeroUsize;
+
 use crate::chains::{get_chain, Chain};
-use clap::{command, Parser};
+use clap::{Parser, ValueEnum};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockTag {
+    Latest,
+    Safe,
+    Finalized,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceAggregation {
+    Mean,
+    Median,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceSource {
+    Dex,
+    Oracle,
+    OracleFallback,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PricingMode {
+    First,
+    Weighted,
+}
 
 #[derive(Parser, Debug)]
 #[command(name = "ETH Snoop", about = "ETH logs and event indexer.")]
 pub struct IndexerArgs {
     #[arg(
         long,
-        help = "Number of blocks to fetch in each batch of logs.",
+        help = "Block tag followed as the indexing target (latest, safe or finalized).",
+        value_enum,
+        default_value_t = BlockTag::Latest
+    )]
+    pub block_tag: BlockTag,
+
+    #[arg(
+        long,
+        help = "Number of times a batch failing with a non-network error is retried before the sync stops with the error.",
+        default_value_t = 3
+    )]
+    pub batch_retries: u32,
+
+    #[arg(
+        long,
+        help = "Minimum number of blocks to fetch in each batch of logs, batches grow with the eth_getLogs range of the chain.",
         default_value_t = 50
     )]
     pub batch_size: usize,
 
     #[arg(
         long,
-        help = "Specifies the target chain/network to index (e.g. 'mainnet', 'testnet').",
-        default_value_t = String::from("mainnet")
+        help = "Chains/networks to index, each into its own database schema (e.g. 'mainnet', 'testnet' or 'mainnet,testnet').",
+        default_value = "mainnet",
+        value_delimiter = ','
+    )]
+    pub chain: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Path to a TOML or JSON file with additional chain definitions (overrides built-in chains with the same name)."
     )]
-    pub chain: String,
+    pub chains_config: Option<String>,
+
+    #[arg(
+        long,
+        help = "Number of blocks to stay behind the latest block.",
+        default_value_t = 0
+    )]
+    pub confirmations: i32,
+
+    #[arg(
+        long,
+        help = "Prices tokens through the pairs of every factory of the chain instead of only the first one.",
+        default_value_t = false
+    )]
+    pub cross_factory_pricing: bool,
 
     #[arg(
         long,
@@ -24,6 +88,27 @@ pub struct IndexerArgs {
     )]
     pub database: String,
 
+    #[arg(
+        long,
+        help = "Seconds to wait for a database connection from the pool.",
+        default_value_t = 30
+    )]
+    pub db_connection_timeout: u64,
+
+    #[arg(
+        long,
+        help = "Seconds an idle database connection is kept in the pool.",
+        default_value_t = 600
+    )]
+    pub db_idle_timeout: u64,
+
+    #[arg(
+        long,
+        help = "Maximum number of database connections in the pool.",
+        default_value_t = 10
+    )]
+    pub db_pool_size: u32,
+
     #[arg(
         long,
         help = "Enables verbose (debug-level) logging output.",
@@ -33,38 +118,293 @@ pub struct IndexerArgs {
 
     #[arg(
         long,
-        help = "URL of the RPC endpoint to fetch chain data and logs."
+        help = "Maximum number of entries of each entity type kept in memory between batches.",
+        default_value_t = NonZeroUsize::new(10_000).unwrap()
+    )]
+    pub entity_cache_size: NonZeroUsize,
+
+    #[arg(
+        long,
+        help = "How the stable pair prices are combined into the ETH price (mean or median), weighted by reserves.",
+        value_enum,
+        default_value_t = PriceAggregation::Mean
+    )]
+    pub eth_price_aggregation: PriceAggregation,
+
+    #[arg(
+        long,
+        help = "Number of upcoming batches whose logs are downloaded while the current batch is processed.",
+        default_value_t = 4
+    )]
+    pub prefetch_chunks: usize,
+
+    #[arg(
+        long,
+        help = "Source of the native token USD price (dex, oracle or oracle-fallback when the stable pairs are illiquid).",
+        value_enum,
+        default_value_t = PriceSource::Dex
+    )]
+    pub price_source: PriceSource,
+
+    #[arg(
+        long,
+        help = "How derived ETH prices are picked from the whitelist pairs (first or weighted).",
+        value_enum,
+        default_value_t = PricingMode::First
+    )]
+    pub pricing_mode: PricingMode,
+
+    #[arg(
+        long,
+        help = "Number of blocks behind the chain head tracked for reorganizations.",
+        default_value_t = 64
     )]
-    pub rpc: String,
+    pub reorg_depth: i32,
+
+    #[arg(
+        long,
+        help = "URLs of the RPC endpoints to fetch chain data and logs (repeat the flag or separate with commas, prefix with '<chain>=' when indexing several chains).",
+        required = true,
+        value_delimiter = ','
+    )]
+    pub rpc: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Base delay in milliseconds between retries of failed RPC requests.",
+        default_value_t = 500
+    )]
+    pub rpc_backoff_ms: u64,
+
+    #[arg(
+        long,
+        help = "Maximum number of blocks an RPC endpoint may trail the others before it is avoided (0 disables the check).",
+        default_value_t = 0
+    )]
+    pub rpc_max_lag: u64,
+
+    #[arg(
+        long,
+        help = "Number of times a failed RPC request is retried.",
+        default_value_t = 5
+    )]
+    pub rpc_retries: u32,
+
+    #[arg(
+        long,
+        help = "Also prices tokens through an intermediate token when deriving ETH prices.",
+        default_value_t = false
+    )]
+    pub two_hop_pricing: bool,
+
+    #[arg(
+        long,
+        help = "URL of a WebSocket RPC endpoint to follow new blocks and logs through subscriptions (prefix with '<chain>=' when indexing several chains).",
+        value_delimiter = ','
+    )]
+    pub ws: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Config {
+    pub batch_retries: u32,
     pub batch_size: usize,
+    pub block_tag: BlockTag,
     pub chain: Chain,
+    pub confirmations: i32,
+    pub cross_factory_pricing: bool,
+    pub db_connection_timeout: u64,
+    pub db_idle_timeout: u64,
+    pub db_pool_size: u32,
     pub db_url: String,
     pub debug: bool,
-    pub rpc: String,
+    pub entity_cache_size: NonZeroUsize,
+    pub eth_price_aggregation: PriceAggregation,
+    pub prefetch_chunks: usize,
+    pub price_source: PriceSource,
+    pub pricing_mode: PricingMode,
+    pub reorg_depth: i32,
+    pub rpc: Vec<String>,
+    pub rpc_backoff_ms: u64,
+    pub rpc_max_lag: u64,
+    pub rpc_retries: u32,
+    pub two_hop_pricing: bool,
+    pub ws: Option<String>,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self::new()
+// Assigns each URL to a chain through its '<chain>=' prefix. Unprefixed
+// URLs are only accepted when a single chain is indexed.
+fn chain_urls(urls: &[String], chains: &[String]) -> Vec<Vec<String>> {
+    let mut chain_urls = vec![Vec::new(); chains.len()];
+
+    for url in urls {
+        let prefixed = url.split_once('=').and_then(|(chain, url)| {
+            chains
+                .iter()
+                .position(|name| name == chain)
+                .map(|index| (index, url))
+        });
+
+        match prefixed {
+            Some((index, url)) => chain_urls[index].push(url.to_owned()),
+            None if chains.len() == 1 => chain_urls[0].push(url.to_owned()),
+            None => panic!(
+                "{} must be prefixed with the chain name when indexing several chains",
+                url
+            ),
+        }
     }
+
+    chain_urls
 }
 
 impl Config {
-    pub fn new() -> Self {
+    pub fn from_args() -> Vec<Self> {
         let args = IndexerArgs::parse();
 
-        let chain = get_chain(args.chain);
+        let mut chains = args.chain.clone();
+        chains.sort();
+        chains.dedup();
+
+        if chains.len() != args.chain.len() {
+            panic!("a chain can only be indexed once");
+        }
+
+        let rpc = chain_urls(&args.rpc, &args.chain);
+        let ws = chain_urls(&args.ws, &args.chain);
+
+        args.chain
+            .iter()
+            .zip(rpc)
+            .zip(ws)
+            .map(|((chain, rpc), ws)| Self::new(&args, chain, rpc, ws))
+            .collect()
+    }
+
+    fn new(
+        args: &IndexerArgs,
+        chain: &str,
+        rpc: Vec<String>,
+        mut ws: Vec<String>,
+    ) -> Self {
+        let chain =
+            get_chain(chain.to_owned(), args.chains_config.as_deref());
+
+        if rpc.is_empty() {
+            panic!("no RPC endpoint configured for chain {}", chain.name);
+        }
+
+        if ws.len() > 1 {
+            panic!(
+                "only one websocket endpoint can be used for chain {}",
+                chain.name
+            );
+        }
+
+        let ws = ws.pop();
+
+        if args.confirmations > 0 && args.block_tag != BlockTag::Latest {
+            panic!(
+                "confirmations can only be used with the latest block tag"
+            );
+        }
+
+        if ws.is_some() && args.block_tag != BlockTag::Latest {
+            panic!("websocket mode can only be used with the latest block tag");
+        }
+
+        if args.price_source != PriceSource::Dex
+            && chain.native_usd_oracle.is_none()
+        {
+            panic!("no price oracle configured for chain {}", chain.name);
+        }
 
         Self {
+            batch_retries: args.batch_retries,
             batch_size: args.batch_size,
+            block_tag: args.block_tag,
             chain,
-            db_url: args.database,
+            confirmations: args.confirmations,
+            cross_factory_pricing: args.cross_factory_pricing,
+            db_connection_timeout: args.db_connection_timeout,
+            db_idle_timeout: args.db_idle_timeout,
+            db_pool_size: args.db_pool_size,
+            db_url: args.database.clone(),
             debug: args.debug,
-            rpc: args.rpc,
+            entity_cache_size: args.entity_cache_size,
+            eth_price_aggregation: args.eth_price_aggregation,
+            prefetch_chunks: args.prefetch_chunks,
+            price_source: args.price_source,
+            pricing_mode: args.pricing_mode,
+            reorg_depth: args.reorg_depth,
+            rpc,
+            rpc_backoff_ms: args.rpc_backoff_ms,
+            rpc_max_lag: args.rpc_max_lag,
+            rpc_retries: args.rpc_retries,
+            two_hop_pricing: args.two_hop_pricing,
+            ws,
         }
     }
+
+    pub fn sync_mode(&self) -> String {
+        match self.block_tag {
+            BlockTag::Latest if self.confirmations > 0 => {
+                format!("confirmations:{}", self.confirmations)
+            }
+            BlockTag::Latest => "latest".to_owned(),
+            BlockTag::Safe => "safe".to_owned(),
+            BlockTag::Finalized => "finalized".to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn assigns_prefixed_urls_to_their_chain() {
+        let urls = strings(&[
+            "mainnet=https://mainnet.example",
+            "testnet=https://testnet.example",
+            "mainnet=https://backup.example",
+        ]);
+
+        assert_eq!(
+            chain_urls(&urls, &strings(&["mainnet", "testnet"])),
+            vec![
+                strings(&[
+                    "https://mainnet.example",
+                    "https://backup.example"
+                ]),
+                strings(&["https://testnet.example"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_unprefixed_urls_for_a_single_chain() {
+        let urls = strings(&[
+            "https://testnet.example",
+            "https://rpc.example/?key=secret",
+        ]);
+
+        assert_eq!(
+            chain_urls(&urls, &strings(&["testnet"])),
+            vec![urls.clone()]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "must be prefixed with the chain name")]
+    fn rejects_unprefixed_urls_for_several_chains() {
+        chain_urls(
+            &strings(&["https://testnet.example"]),
+            &strings(&["mainnet", "testnet"]),
+        );
+    }
 }
@@ -0,0 +1,54 @@
+use std::{collections::HashMap, num::NonZeroUsize};
+
+use lru::LruCache;
+
+use super::models::{
+    bundle::DatabaseBundle,
+    data::{
+        DatabaseDexDayData, DatabasePairDayData, DatabasePairHourData,
+        DatabaseTokenDayData,
+    },
+    factory::DatabaseFactory,
+    pair::DatabasePair,
+    token::DatabaseToken,
+};
+
+// Committed entities kept in memory between chunks. Entries are only added
+// after they are read from or written to the database, so the cache never
+// holds state that could be lost by a failed transaction.
+pub struct EntityCache {
+    pub factories: HashMap<String, DatabaseFactory>,
+    pub bundle: Option<DatabaseBundle>,
+    pub pairs: LruCache<String, DatabasePair>,
+    pub tokens: LruCache<String, DatabaseToken>,
+    pub pairs_day_data: LruCache<String, DatabasePairDayData>,
+    pub pairs_hour_data: LruCache<String, DatabasePairHourData>,
+    pub tokens_day_data: LruCache<String, DatabaseTokenDayData>,
+    pub dex_day_data: LruCache<String, DatabaseDexDayData>,
+}
+
+impl EntityCache {
+    pub fn new(size: NonZeroUsize) -> Self {
+        Self {
+            factories: HashMap::new(),
+            bundle: None,
+            pairs: LruCache::new(size),
+            tokens: LruCache::new(size),
+            pairs_day_data: LruCache::new(size),
+            pairs_hour_data: LruCache::new(size),
+            tokens_day_data: LruCache::new(size),
+            dex_day_data: LruCache::new(size),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.factories.clear();
+        self.bundle = None;
+        self.pairs.clear();
+        self.tokens.clear();
+        self.pairs_day_data.clear();
+        self.pairs_hour_data.clear();
+        self.tokens_day_data.clear();
+        self.dex_day_data.clear();
+    }
+}
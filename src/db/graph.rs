@@ -0,0 +1,163 @@
+use std::collections::{HashMap, HashSet};
+
+// Index from token pairs to the pair addresses of every factory, in creation
+// order. Pairs never change their tokens, so the index only grows with new
+// pairs and is rebuilt after a rollback. Reserves are not stored here, they
+// are read from the caches when a pair is used. Pairs created in a chunk
+// are staged in the chunk cache and merged into the shared graph once the
+// chunk is stored.
+#[derive(Default)]
+pub struct PairGraph {
+    pairs: HashMap<(String, String), Vec<(String, String)>>,
+    neighbours: HashMap<String, HashSet<String>>,
+}
+
+impl PairGraph {
+    pub fn insert(
+        &mut self,
+        token0: &str,
+        token1: &str,
+        factory: &str,
+        pair: &str,
+    ) {
+        let pairs =
+            self.pairs.entry(Self::key(token0, token1)).or_default();
+
+        if pairs.iter().any(|(_, known_pair)| known_pair == pair) {
+            return;
+        }
+
+        pairs.push((factory.to_owned(), pair.to_owned()));
+
+        self.neighbours
+            .entry(token0.to_owned())
+            .or_default()
+            .insert(token1.to_owned());
+
+        self.neighbours
+            .entry(token1.to_owned())
+            .or_default()
+            .insert(token0.to_owned());
+    }
+
+    // Returns the (factory, pair) entries for the tokens.
+    pub fn get(
+        &self,
+        token_a: &str,
+        token_b: &str,
+    ) -> &[(String, String)] {
+        match self.pairs.get(&Self::key(token_a, token_b)) {
+            Some(pairs) => pairs,
+            None => &[],
+        }
+    }
+
+    pub fn merge(&mut self, other: PairGraph) {
+        for ((token0, token1), pairs) in other.pairs {
+            for (factory, pair) in pairs {
+                self.insert(&token0, &token1, &factory, &pair);
+            }
+        }
+    }
+
+    pub fn neighbours(&self, token: &str) -> Option<&HashSet<String>> {
+        self.neighbours.get(token)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.values().map(|pairs| pairs.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    fn key(token_a: &str, token_b: &str) -> (String, String) {
+        if token_a <= token_b {
+            (token_a.to_owned(), token_b.to_owned())
+        } else {
+            (token_b.to_owned(), token_a.to_owned())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(factory: &str, pair: &str) -> (String, String) {
+        (factory.to_owned(), pair.to_owned())
+    }
+
+    #[test]
+    fn finds_pairs_in_both_token_orders() {
+        let mut graph = PairGraph::default();
+        graph.insert("0xb", "0xa", "uniswap", "0x1");
+
+        assert_eq!(graph.get("0xa", "0xb"), &[pair("uniswap", "0x1")]);
+        assert_eq!(graph.get("0xb", "0xa"), &[pair("uniswap", "0x1")]);
+        assert!(graph.get("0xa", "0xc").is_empty());
+    }
+
+    #[test]
+    fn keeps_the_creation_order_across_factories() {
+        let mut graph = PairGraph::default();
+        graph.insert("0xa", "0xb", "uniswap", "0x1");
+        graph.insert("0xa", "0xb", "sushiswap", "0x2");
+
+        assert_eq!(
+            graph.get("0xa", "0xb"),
+            &[pair("uniswap", "0x1"), pair("sushiswap", "0x2")]
+        );
+        assert_eq!(graph.len(), 2);
+    }
+
+    #[test]
+    fn ignores_a_pair_inserted_twice() {
+        let mut graph = PairGraph::default();
+        graph.insert("0xa", "0xb", "uniswap", "0x1");
+        graph.insert("0xb", "0xa", "uniswap", "0x1");
+
+        assert_eq!(graph.len(), 1);
+    }
+
+    #[test]
+    fn tracks_the_neighbours_of_both_tokens() {
+        let mut graph = PairGraph::default();
+
+        assert!(graph.is_empty());
+        assert!(graph.neighbours("0xa").is_none());
+
+        graph.insert("0xa", "0xb", "uniswap", "0x1");
+        graph.insert("0xa", "0xc", "uniswap", "0x2");
+
+        assert_eq!(
+            graph.neighbours("0xa"),
+            Some(&HashSet::from(["0xb".to_owned(), "0xc".to_owned()]))
+        );
+        assert_eq!(
+            graph.neighbours("0xc"),
+            Some(&HashSet::from(["0xa".to_owned()]))
+        );
+    }
+
+    #[test]
+    fn merges_staged_pairs_without_duplicates() {
+        let mut graph = PairGraph::default();
+        graph.insert("0xa", "0xb", "uniswap", "0x1");
+
+        let mut staged = PairGraph::default();
+        staged.insert("0xa", "0xb", "uniswap", "0x1");
+        staged.insert("0xa", "0xb", "sushiswap", "0x2");
+        staged.insert("0xb", "0xc", "uniswap", "0x3");
+
+        graph.merge(staged);
+
+        assert_eq!(
+            graph.get("0xa", "0xb"),
+            &[pair("uniswap", "0x1"), pair("sushiswap", "0x2")]
+        );
+        assert_eq!(graph.get("0xc", "0xb"), &[pair("uniswap", "0x3")]);
+        assert_eq!(graph.len(), 3);
+    }
+}
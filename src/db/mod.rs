@@ -1,26 +1,51 @@
+pub mod cache;
+pub mod graph;
 pub mod models;
+pub mod reorg;
 pub mod schema;
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use crate::{
+    chains::Chain, configs::Config, handlers::registry::EventHandler,
+};
 
-use crate::chains::Chain;
+use alloy::rpc::types::Log;
+use bigdecimal::BigDecimal;
+use cache::EntityCache;
+use graph::PairGraph;
+use reorg::{pre_images, rollback_block, JournalEntry};
 
 use diesel::{
-    upsert::excluded, BoolExpressionMethods, Connection,
-    ExpressionMethods, OptionalExtension, PgConnection, QueryDsl,
-    QueryResult, RunQueryDsl,
+    connection::SimpleConnection,
+    r2d2::{
+        ConnectionManager, CustomizeConnection, Pool, PooledConnection,
+        State,
+    },
+    sql_query,
+    sql_types::{Array, Integer, Text},
+    upsert::excluded,
+    Connection, ExpressionMethods, OptionalExtension, PgConnection,
+    QueryDsl, QueryResult, RunQueryDsl,
 };
 use diesel_migrations::{
     embed_migrations, EmbeddedMigrations, MigrationHarness,
 };
 use log::*;
 use models::{
+    block::DatabaseBlock,
     bundle::DatabaseBundle,
     burn::DatabaseBurn,
     data::{
         DatabaseDexDayData, DatabasePairDayData, DatabasePairHourData,
         DatabaseTokenDayData,
     },
+    eth_price::DatabaseEthPrice,
+    event::DatabaseEvent,
     factory::DatabaseFactory,
     mint::DatabaseMint,
     pair::DatabasePair,
@@ -31,15 +56,22 @@ use models::{
 };
 
 use schema::{
-    bundles, burns, dex_day_data, factories, mints, pair_day_data,
-    pair_hour_data, pairs, swaps, sync_state, token_day_data, tokens,
-    transactions,
+    blocks, bundles, burns, dex_day_data, eth_price_history, events,
+    factories, journal, mints, pair_day_data, pair_hour_data, pairs,
+    swaps, sync_state, token_day_data, tokens, transactions,
 };
 
 pub struct StorageCache {
     pub db: Database,
-    pub factory: DatabaseFactory,
+    pub first_block: i32,
+    pub last_block: i32,
+    pub block: Option<DatabaseBlock>,
+    pub blocks: HashMap<i32, DatabaseBlock>,
+    pub factories: HashMap<String, DatabaseFactory>,
     pub bundle: DatabaseBundle,
+    pub oracle_eth_price: Option<BigDecimal>,
+    pub eth_prices: HashMap<i32, DatabaseEthPrice>,
+    pub events: Vec<DatabaseEvent>,
     pub pairs: HashMap<String, DatabasePair>,
     pub tokens: HashMap<String, DatabaseToken>,
     pub transactions: HashMap<String, DatabaseTransaction>,
@@ -50,10 +82,121 @@ pub struct StorageCache {
     pub pairs_hour_data: HashMap<String, DatabasePairHourData>,
     pub tokens_day_data: HashMap<String, DatabaseTokenDayData>,
     pub dex_day_data: HashMap<String, DatabaseDexDayData>,
+    pub pair_graph: PairGraph,
+    writes: Vec<Write>,
 }
 
+// Extra writes of the event handlers, run in the transaction that stores
+// the chunk.
+pub type Write =
+    Box<dyn FnOnce(&mut PgConnection) -> QueryResult<()> + Send + Sync>;
+
 impl StorageCache {
-    pub async fn store(&self) {
+    pub async fn new(
+        db: &Database,
+        first_block: i32,
+        last_block: i32,
+        block: Option<DatabaseBlock>,
+    ) -> Self {
+        let (factories, bundle) =
+            tokio::join!(db.get_factories(), db.get_bundle());
+
+        let mut blocks = HashMap::new();
+
+        if let Some(block) = &block {
+            blocks.insert(block.number, block.clone());
+        }
+
+        Self {
+            db: db.clone(),
+            first_block,
+            last_block,
+            block,
+            blocks,
+            factories,
+            bundle,
+            oracle_eth_price: None,
+            eth_prices: HashMap::new(),
+            events: Vec::new(),
+            pairs: HashMap::new(),
+            tokens: HashMap::new(),
+            transactions: HashMap::new(),
+            mints: HashMap::new(),
+            swaps: HashMap::new(),
+            burns: HashMap::new(),
+            pairs_day_data: HashMap::new(),
+            pairs_hour_data: HashMap::new(),
+            tokens_day_data: HashMap::new(),
+            dex_day_data: HashMap::new(),
+            pair_graph: PairGraph::default(),
+            writes: Vec::new(),
+        }
+    }
+
+    // Returns the (factory, pair) entries of the committed and the staged
+    // pairs for the tokens.
+    pub fn graph_pairs(
+        &self,
+        token_a: &str,
+        token_b: &str,
+    ) -> Vec<(String, String)> {
+        let mut pairs = self
+            .db
+            .pair_graph
+            .read()
+            .unwrap()
+            .get(token_a, token_b)
+            .to_vec();
+
+        pairs.extend_from_slice(self.pair_graph.get(token_a, token_b));
+
+        pairs
+    }
+
+    pub fn graph_neighbours(&self, token: &str) -> HashSet<String> {
+        let mut neighbours = self
+            .db
+            .pair_graph
+            .read()
+            .unwrap()
+            .neighbours(token)
+            .cloned()
+            .unwrap_or_default();
+
+        if let Some(staged) = self.pair_graph.neighbours(token) {
+            neighbours.extend(staged.iter().cloned());
+        }
+
+        neighbours
+    }
+
+    pub fn block_timestamp(&self, log: &Log) -> i32 {
+        match log.block_timestamp {
+            Some(timestamp) => timestamp as i32,
+            None => {
+                let block_number = log.block_number.unwrap() as i32;
+
+                self.blocks.get(&block_number).unwrap().timestamp
+            }
+        }
+    }
+
+    pub fn write<F>(&mut self, write: F)
+    where
+        F: FnOnce(&mut PgConnection) -> QueryResult<()>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.writes.push(Box::new(write));
+    }
+
+    pub async fn store(&mut self) {
+        let writes = std::mem::take(&mut self.writes);
+
+        let blocks: Vec<DatabaseBlock> =
+            self.blocks.clone().into_values().collect();
+
         let pairs: Vec<DatabasePair> =
             self.pairs.clone().into_values().collect();
 
@@ -84,35 +227,199 @@ impl StorageCache {
         let dex_day_data: Vec<DatabaseDexDayData> =
             self.dex_day_data.clone().into_values().collect();
 
-        tokio::join!(
-            self.db.update_factory(&self.factory),
-            self.db.update_bundle(&self.bundle),
-            self.db.update_pairs(&pairs),
-            self.db.update_tokens(&tokens),
-            self.db.update_burns(&burns),
-            self.db.update_mints(&mints),
-            self.db.update_swaps(&swaps),
-            self.db.update_transactions(&transactions),
-            self.db.update_dexes_day_data(&dex_day_data),
-            self.db.update_pairs_day_data(&pairs_day_data),
-            self.db.update_pairs_hour_data(&pairs_hour_data),
-            self.db.update_tokens_day_data(&tokens_day_data)
-        );
+        let eth_prices: Vec<DatabaseEthPrice> =
+            self.eth_prices.clone().into_values().collect();
+
+        let events = std::mem::take(&mut self.events);
+
+        let journal = match self.block {
+            Some(_) => self.journal_entities(),
+            None => Vec::new(),
+        };
+
+        let factories: Vec<DatabaseFactory> =
+            self.factories.clone().into_values().collect();
+        let bundle = self.bundle.clone();
+        let first_block = self.first_block;
+        let last_block = self.last_block;
+
+        self.db
+            .run(move |connection| {
+                connection.transaction::<_, diesel::result::Error, _>(
+                    |connection| {
+                        for (table, ids) in journal {
+                            Database::journal(
+                                connection,
+                                first_block,
+                                last_block,
+                                table,
+                                ids,
+                            )?;
+                        }
+
+                        Database::update_blocks(connection, &blocks)?;
+
+                        Database::update_tokens(connection, &tokens)?;
+                        Database::update_pairs(connection, &pairs)?;
+                        Database::update_factories(
+                            connection, &factories,
+                        )?;
+                        Database::update_bundle(connection, &bundle)?;
+                        Database::update_eth_prices(
+                            connection,
+                            &eth_prices,
+                        )?;
+                        Database::update_transactions(
+                            connection,
+                            &transactions,
+                        )?;
+                        Database::update_burns(connection, &burns)?;
+                        Database::update_mints(connection, &mints)?;
+                        Database::update_swaps(connection, &swaps)?;
+                        Database::update_events(connection, &events)?;
+                        Database::update_dexes_day_data(
+                            connection,
+                            &dex_day_data,
+                        )?;
+                        Database::update_pairs_day_data(
+                            connection,
+                            &pairs_day_data,
+                        )?;
+                        Database::update_pairs_hour_data(
+                            connection,
+                            &pairs_hour_data,
+                        )?;
+                        Database::update_tokens_day_data(
+                            connection,
+                            &tokens_day_data,
+                        )?;
+
+                        for write in writes {
+                            write(connection)?;
+                        }
+
+                        Database::update_state(connection, last_block)?;
+
+                        Ok(())
+                    },
+                )
+            })
+            .await
+            .unwrap();
+
+        self.db
+            .pair_graph
+            .write()
+            .unwrap()
+            .merge(std::mem::take(&mut self.pair_graph));
+
+        let mut entities = self.db.entities.lock().unwrap();
+
+        for (id, factory) in self.factories.iter() {
+            entities.factories.insert(id.clone(), factory.clone());
+        }
+        entities.bundle = Some(self.bundle.clone());
+
+        for (id, pair) in self.pairs.iter() {
+            entities.pairs.put(id.clone(), pair.clone());
+        }
+
+        for (id, token) in self.tokens.iter() {
+            entities.tokens.put(id.clone(), token.clone());
+        }
+
+        for (id, data) in self.pairs_day_data.iter() {
+            entities.pairs_day_data.put(id.clone(), data.clone());
+        }
+
+        for (id, data) in self.pairs_hour_data.iter() {
+            entities.pairs_hour_data.put(id.clone(), data.clone());
+        }
+
+        for (id, data) in self.tokens_day_data.iter() {
+            entities.tokens_day_data.put(id.clone(), data.clone());
+        }
+
+        for (id, data) in self.dex_day_data.iter() {
+            entities.dex_day_data.put(id.clone(), data.clone());
+        }
+    }
+
+    fn journal_entities(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![
+            ("tokens", self.tokens.keys().cloned().collect()),
+            ("pairs", self.pairs.keys().cloned().collect()),
+            ("factories", self.factories.keys().cloned().collect()),
+            ("bundles", vec![self.bundle.id.clone()]),
+            ("transactions", self.transactions.keys().cloned().collect()),
+            ("mints", self.mints.keys().cloned().collect()),
+            ("burns", self.burns.keys().cloned().collect()),
+            ("swaps", self.swaps.keys().cloned().collect()),
+            ("dex_day_data", self.dex_day_data.keys().cloned().collect()),
+            (
+                "pair_day_data",
+                self.pairs_day_data.keys().cloned().collect(),
+            ),
+            (
+                "pair_hour_data",
+                self.pairs_hour_data.keys().cloned().collect(),
+            ),
+            (
+                "token_day_data",
+                self.tokens_day_data.keys().cloned().collect(),
+            ),
+        ]
     }
 }
 
 pub const MIGRATIONS: EmbeddedMigrations =
     embed_migrations!("migrations/");
 
+// Ordered so that referenced rows are restored before the rows that
+// reference them.
+pub const JOURNAL_TABLES: [&str; 12] = [
+    "tokens",
+    "pairs",
+    "factories",
+    "bundles",
+    "transactions",
+    "mints",
+    "burns",
+    "swaps",
+    "dex_day_data",
+    "pair_day_data",
+    "pair_hour_data",
+    "token_day_data",
+];
+
+// Every chain is stored in its own schema, so connections resolve the
+// unqualified table names against it.
+#[derive(Debug)]
+struct ChainSchema(&'static str);
+
+impl CustomizeConnection<PgConnection, diesel::r2d2::Error>
+    for ChainSchema
+{
+    fn on_acquire(
+        &self,
+        connection: &mut PgConnection,
+    ) -> Result<(), diesel::r2d2::Error> {
+        connection
+            .batch_execute(&format!("SET search_path TO \"{}\"", self.0))
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
 #[derive(Clone)]
 pub struct Database {
     pub chain: Chain,
-    pub db_url: String,
+    pub entities: Arc<Mutex<EntityCache>>,
+    pub pair_graph: Arc<RwLock<PairGraph>>,
+    pub pool: Pool<ConnectionManager<PgConnection>>,
 }
 
 pub enum DatabaseKeys {
     State,
-    Factory,
     Bundle,
     Logs,
     Burns,
@@ -132,7 +439,6 @@ impl DatabaseKeys {
     pub fn as_str(&self) -> &'static str {
         match self {
             DatabaseKeys::State => "sync_state",
-            DatabaseKeys::Factory => "taya_swap",
             DatabaseKeys::Bundle => "bundle",
             DatabaseKeys::Logs => "logs",
             DatabaseKeys::Burns => "burns",
@@ -151,239 +457,521 @@ impl DatabaseKeys {
 }
 
 impl Database {
-    pub async fn new(db_url: String, chain: Chain) -> Self {
-        info!("Starting database service");
-
-        let mut db = PgConnection::establish(&db_url).unwrap();
+    pub async fn new(config: &Config) -> Self {
+        info!(target: config.chain.name, "Starting database service");
+
+        let manager =
+            ConnectionManager::<PgConnection>::new(config.db_url.clone());
+
+        let pool = Pool::builder()
+            .connection_customizer(Box::new(ChainSchema(
+                config.chain.name,
+            )))
+            .max_size(config.db_pool_size)
+            .connection_timeout(Duration::from_secs(
+                config.db_connection_timeout,
+            ))
+            .idle_timeout(Some(Duration::from_secs(
+                config.db_idle_timeout,
+            )))
+            .build(manager)
+            .expect("unable to connect to the database");
+
+        let mut db = pool.get().unwrap();
+
+        db.batch_execute(&format!(
+            "CREATE SCHEMA IF NOT EXISTS \"{}\"",
+            config.chain.name
+        ))
+        .unwrap();
 
         db.run_pending_migrations(MIGRATIONS).unwrap();
 
-        Self { chain, db_url }
-    }
+        let pair_graph = Database::load_pair_graph(&mut db).unwrap();
+
+        info!(target: config.chain.name, "Loaded {} pairs into the pair graph", pair_graph.len());
 
-    pub fn get_connection(&self) -> PgConnection {
-        PgConnection::establish(&self.db_url)
-            .expect("unable to connect to the database")
+        Self {
+            chain: config.chain.clone(),
+            entities: Arc::new(Mutex::new(EntityCache::new(
+                config.entity_cache_size,
+            ))),
+            pair_graph: Arc::new(RwLock::new(pair_graph)),
+            pool,
+        }
     }
 
-    pub async fn get_last_block_indexed(&self) -> i32 {
-        let mut connection = self.get_connection();
+    fn load_pair_graph(
+        connection: &mut PgConnection,
+    ) -> QueryResult<PairGraph> {
+        let pairs = pairs::dsl::pairs
+            .select((
+                pairs::dsl::id,
+                pairs::dsl::factory,
+                pairs::dsl::token0,
+                pairs::dsl::token1,
+            ))
+            .order(pairs::dsl::created_at_block_number)
+            .load::<(String, String, String, String)>(connection)?;
 
-        let number: QueryResult<i32> = sync_state::dsl::sync_state
-            .select(sync_state::dsl::last_block_indexed)
-            .filter(sync_state::dsl::id.eq("sync_state"))
-            .first::<i32>(&mut connection);
+        let mut pair_graph = PairGraph::default();
 
-        match number {
-            Ok(block) => block,
-            Err(_) => {
-                let new_sync_state = DatabaseSyncState::new();
+        for (pair, factory, token0, token1) in pairs {
+            pair_graph.insert(&token0, &token1, &factory, &pair);
+        }
 
-                diesel::insert_into(sync_state::dsl::sync_state)
-                    .values(new_sync_state.clone())
-                    .execute(&mut connection)
-                    .unwrap();
+        Ok(pair_graph)
+    }
 
-                new_sync_state.last_block_indexed
-            }
-        }
+    pub fn get_connection(
+        &self,
+    ) -> PooledConnection<ConnectionManager<PgConnection>> {
+        self.pool.get().expect("unable to connect to the database")
     }
 
-    pub async fn get_factory(&self) -> DatabaseFactory {
-        let mut connection = self.get_connection();
+    // Diesel is synchronous, so queries run on the blocking thread pool
+    // to keep the tokio workers free.
+    pub async fn run<F, T>(&self, query: F) -> T
+    where
+        F: FnOnce(&mut PgConnection) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut connection =
+                pool.get().expect("unable to connect to the database");
+
+            query(&mut connection)
+        })
+        .await
+        .unwrap()
+    }
 
-        match factories::dsl::factories
-            .find(DatabaseKeys::Factory.as_str())
-            .first::<DatabaseFactory>(&mut connection)
-        {
-            Ok(factory) => factory,
-            Err(_) => {
-                let new_factory = DatabaseFactory::new();
+    pub fn pool_stats(&self) -> State {
+        self.pool.state()
+    }
 
-                diesel::insert_into(factories::dsl::factories)
-                    .values(new_factory.clone())
-                    .execute(&mut connection)
+    pub async fn get_last_block_indexed(&self) -> i32 {
+        self.run(move |connection| {
+            sync_state::dsl::sync_state
+                .select(sync_state::dsl::last_block_indexed)
+                .find(DatabaseKeys::State.as_str())
+                .first::<i32>(connection)
+                .optional()
+                .unwrap()
+                .unwrap_or(0)
+        })
+        .await
+    }
+
+    // Returns the mode of the indexed data when it differs from the
+    // requested one.
+    pub async fn check_sync_mode(&self, mode: &str) -> Result<(), String> {
+        let mode = mode.to_owned();
+
+        self.run(move |connection| {
+            let state = sync_state::dsl::sync_state
+                .find(DatabaseKeys::State.as_str())
+                .first::<DatabaseSyncState>(connection)
+                .optional()
+                .unwrap();
+
+            match state {
+                Some(state) if state.mode == mode => Ok(()),
+                Some(state) if state.last_block_indexed == 0 => {
+                    diesel::update(
+                        sync_state::dsl::sync_state
+                            .find(DatabaseKeys::State.as_str()),
+                    )
+                    .set(sync_state::dsl::mode.eq(mode))
+                    .execute(connection)
                     .unwrap();
 
-                new_factory
+                    Ok(())
+                }
+                Some(state) => Err(state.mode),
+                None => {
+                    diesel::insert_into(sync_state::dsl::sync_state)
+                        .values(DatabaseSyncState::new(mode))
+                        .execute(connection)
+                        .unwrap();
+
+                    Ok(())
+                }
             }
-        }
+        })
+        .await
     }
 
-    pub async fn get_bundle(&self) -> DatabaseBundle {
-        let mut connection: PgConnection = self.get_connection();
+    pub async fn get_factories(&self) -> HashMap<String, DatabaseFactory> {
+        let mut factories = HashMap::new();
+
+        for factory in self.chain.factories {
+            factories.insert(
+                factory.name.to_owned(),
+                self.get_factory(factory.name).await,
+            );
+        }
+
+        factories
+    }
 
-        match bundles::dsl::bundles
-            .find(DatabaseKeys::Bundle.as_str())
-            .first::<DatabaseBundle>(&mut connection)
+    pub async fn get_factory(&self, name: &str) -> DatabaseFactory {
+        if let Some(factory) =
+            self.entities.lock().unwrap().factories.get(name)
         {
-            Ok(bundle) => bundle,
-            Err(_) => {
-                let new_bundle = DatabaseBundle::new();
+            return factory.clone();
+        }
 
-                diesel::insert_into(bundles::dsl::bundles)
-                    .values(new_bundle.clone())
-                    .execute(&mut connection)
-                    .unwrap();
+        let name = name.to_owned();
+
+        let factory = self
+            .run(move |connection| {
+                match factories::dsl::factories
+                    .find(&name)
+                    .first::<DatabaseFactory>(connection)
+                {
+                    Ok(factory) => factory,
+                    Err(_) => {
+                        let new_factory = DatabaseFactory::new(name);
+
+                        diesel::insert_into(factories::dsl::factories)
+                            .values(new_factory.clone())
+                            .execute(connection)
+                            .unwrap();
+
+                        new_factory
+                    }
+                }
+            })
+            .await;
+
+        self.entities
+            .lock()
+            .unwrap()
+            .factories
+            .insert(factory.id.clone(), factory.clone());
 
-                new_bundle
-            }
+        factory
+    }
+
+    pub async fn get_bundle(&self) -> DatabaseBundle {
+        if let Some(bundle) = &self.entities.lock().unwrap().bundle {
+            return bundle.clone();
         }
+
+        let bundle = self
+            .run(move |connection| {
+                match bundles::dsl::bundles
+                    .find(DatabaseKeys::Bundle.as_str())
+                    .first::<DatabaseBundle>(connection)
+                {
+                    Ok(bundle) => bundle,
+                    Err(_) => {
+                        let new_bundle = DatabaseBundle::new();
+
+                        diesel::insert_into(bundles::dsl::bundles)
+                            .values(new_bundle.clone())
+                            .execute(connection)
+                            .unwrap();
+
+                        new_bundle
+                    }
+                }
+            })
+            .await;
+
+        self.entities.lock().unwrap().bundle = Some(bundle.clone());
+
+        bundle
     }
 
     pub async fn get_token(&self, id: &str) -> Option<DatabaseToken> {
-        let mut connection: PgConnection = self.get_connection();
+        if let Some(token) = self.entities.lock().unwrap().tokens.get(id) {
+            return Some(token.clone());
+        }
 
-        tokens::dsl::tokens
-            .find(id)
-            .first::<DatabaseToken>(&mut connection)
-            .optional()
-            .unwrap()
+        let id = id.to_owned();
+
+        let token = self
+            .run(move |connection| {
+                tokens::dsl::tokens
+                    .find(id)
+                    .first::<DatabaseToken>(connection)
+                    .optional()
+                    .unwrap()
+            })
+            .await;
+
+        if let Some(token) = &token {
+            self.entities
+                .lock()
+                .unwrap()
+                .tokens
+                .put(token.id.clone(), token.clone());
+        }
+
+        token
     }
 
     pub async fn get_pair(&self, id: &str) -> Option<DatabasePair> {
-        let mut connection: PgConnection = self.get_connection();
+        if let Some(pair) = self.entities.lock().unwrap().pairs.get(id) {
+            return Some(pair.clone());
+        }
 
-        pairs::dsl::pairs
-            .find(id)
-            .first::<DatabasePair>(&mut connection)
-            .optional()
-            .unwrap()
+        let id = id.to_owned();
+
+        let pair = self
+            .run(move |connection| {
+                pairs::dsl::pairs
+                    .find(id)
+                    .first::<DatabasePair>(connection)
+                    .optional()
+                    .unwrap()
+            })
+            .await;
+
+        if let Some(pair) = &pair {
+            self.entities
+                .lock()
+                .unwrap()
+                .pairs
+                .put(pair.id.clone(), pair.clone());
+        }
+
+        pair
     }
 
     pub async fn get_pair_for_tokens(
         &self,
         token_a: &str,
         token_b: &str,
+        factory: &str,
     ) -> Option<DatabasePair> {
-        let mut connection = self.get_connection();
-
-        pairs::dsl::pairs
-            .filter(
-                (pairs::dsl::token0
-                    .eq(token_a)
-                    .and(pairs::dsl::token1.eq(token_b)))
-                .or(pairs::dsl::token0
-                    .eq(token_b)
-                    .and(pairs::dsl::token1.eq(token_a))),
-            )
-            .first::<DatabasePair>(&mut connection)
-            .optional()
+        let pair = self
+            .pair_graph
+            .read()
             .unwrap()
+            .get(token_a, token_b)
+            .iter()
+            .find(|(pair_factory, _)| pair_factory == factory)
+            .map(|(_, pair)| pair.clone());
+
+        match pair {
+            Some(pair) => self.get_pair(&pair).await,
+            None => None,
+        }
     }
 
     pub async fn get_transaction(
         &self,
         id: &str,
     ) -> Option<DatabaseTransaction> {
-        let mut connection: PgConnection = self.get_connection();
-
-        transactions::dsl::transactions
-            .find(id)
-            .first::<DatabaseTransaction>(&mut connection)
-            .optional()
-            .unwrap()
+        let id = id.to_owned();
+
+        self.run(move |connection| {
+            transactions::dsl::transactions
+                .find(id)
+                .first::<DatabaseTransaction>(connection)
+                .optional()
+                .unwrap()
+        })
+        .await
     }
 
     pub async fn get_mint(&self, id: &str) -> Option<DatabaseMint> {
-        let mut connection: PgConnection = self.get_connection();
-
-        mints::dsl::mints
-            .find(id)
-            .first::<DatabaseMint>(&mut connection)
-            .optional()
-            .unwrap()
+        let id = id.to_owned();
+
+        self.run(move |connection| {
+            mints::dsl::mints
+                .find(id)
+                .first::<DatabaseMint>(connection)
+                .optional()
+                .unwrap()
+        })
+        .await
     }
 
     pub async fn get_burn(&self, id: &str) -> Option<DatabaseBurn> {
-        let mut connection: PgConnection = self.get_connection();
-
-        burns::dsl::burns
-            .find(id)
-            .first::<DatabaseBurn>(&mut connection)
-            .optional()
-            .unwrap()
+        let id = id.to_owned();
+
+        self.run(move |connection| {
+            burns::dsl::burns
+                .find(id)
+                .first::<DatabaseBurn>(connection)
+                .optional()
+                .unwrap()
+        })
+        .await
     }
 
     pub async fn get_dex_day_data(
         &self,
         id: &str,
     ) -> Option<DatabaseDexDayData> {
-        let mut connection: PgConnection = self.get_connection();
+        if let Some(dex_day_data) =
+            self.entities.lock().unwrap().dex_day_data.get(id)
+        {
+            return Some(dex_day_data.clone());
+        }
 
-        dex_day_data::dsl::dex_day_data
-            .find(id)
-            .first::<DatabaseDexDayData>(&mut connection)
-            .optional()
-            .unwrap()
+        let id = id.to_owned();
+
+        let dex_day_data = self
+            .run(move |connection| {
+                dex_day_data::dsl::dex_day_data
+                    .find(id)
+                    .first::<DatabaseDexDayData>(connection)
+                    .optional()
+                    .unwrap()
+            })
+            .await;
+
+        if let Some(dex_day_data) = &dex_day_data {
+            self.entities
+                .lock()
+                .unwrap()
+                .dex_day_data
+                .put(dex_day_data.id.clone(), dex_day_data.clone());
+        }
+
+        dex_day_data
     }
 
     pub async fn get_pair_day_data(
         &self,
         id: &str,
     ) -> Option<DatabasePairDayData> {
-        let mut connection: PgConnection = self.get_connection();
+        if let Some(pair_day_data) =
+            self.entities.lock().unwrap().pairs_day_data.get(id)
+        {
+            return Some(pair_day_data.clone());
+        }
 
-        pair_day_data::dsl::pair_day_data
-            .find(id)
-            .first::<DatabasePairDayData>(&mut connection)
-            .optional()
-            .unwrap()
+        let id = id.to_owned();
+
+        let pair_day_data = self
+            .run(move |connection| {
+                pair_day_data::dsl::pair_day_data
+                    .find(id)
+                    .first::<DatabasePairDayData>(connection)
+                    .optional()
+                    .unwrap()
+            })
+            .await;
+
+        if let Some(pair_day_data) = &pair_day_data {
+            self.entities
+                .lock()
+                .unwrap()
+                .pairs_day_data
+                .put(pair_day_data.id.clone(), pair_day_data.clone());
+        }
+
+        pair_day_data
     }
 
     pub async fn get_pair_hour_data(
         &self,
         id: &str,
     ) -> Option<DatabasePairHourData> {
-        let mut connection: PgConnection = self.get_connection();
+        if let Some(pair_hour_data) =
+            self.entities.lock().unwrap().pairs_hour_data.get(id)
+        {
+            return Some(pair_hour_data.clone());
+        }
 
-        pair_hour_data::dsl::pair_hour_data
-            .find(id)
-            .first::<DatabasePairHourData>(&mut connection)
-            .optional()
-            .unwrap()
+        let id = id.to_owned();
+
+        let pair_hour_data = self
+            .run(move |connection| {
+                pair_hour_data::dsl::pair_hour_data
+                    .find(id)
+                    .first::<DatabasePairHourData>(connection)
+                    .optional()
+                    .unwrap()
+            })
+            .await;
+
+        if let Some(pair_hour_data) = &pair_hour_data {
+            self.entities
+                .lock()
+                .unwrap()
+                .pairs_hour_data
+                .put(pair_hour_data.id.clone(), pair_hour_data.clone());
+        }
+
+        pair_hour_data
     }
 
     pub async fn get_token_day_data(
         &self,
         id: &str,
     ) -> Option<DatabaseTokenDayData> {
-        let mut connection: PgConnection = self.get_connection();
+        if let Some(token_day_data) =
+            self.entities.lock().unwrap().tokens_day_data.get(id)
+        {
+            return Some(token_day_data.clone());
+        }
 
-        token_day_data::dsl::token_day_data
-            .find(id)
-            .first::<DatabaseTokenDayData>(&mut connection)
-            .optional()
-            .unwrap()
+        let id = id.to_owned();
+
+        let token_day_data = self
+            .run(move |connection| {
+                token_day_data::dsl::token_day_data
+                    .find(id)
+                    .first::<DatabaseTokenDayData>(connection)
+                    .optional()
+                    .unwrap()
+            })
+            .await;
+
+        if let Some(token_day_data) = &token_day_data {
+            self.entities
+                .lock()
+                .unwrap()
+                .tokens_day_data
+                .put(token_day_data.id.clone(), token_day_data.clone());
+        }
+
+        token_day_data
     }
 
-    pub async fn update_factory(&self, data: &DatabaseFactory) {
-        let mut connection: PgConnection = self.get_connection();
+    pub fn update_factories(
+        connection: &mut PgConnection,
+        data: &Vec<DatabaseFactory>,
+    ) -> QueryResult<usize> {
+        let mut updated = 0;
+
+        for factory in data {
+            updated += diesel::insert_into(factories::dsl::factories)
+                .values(factory)
+                .on_conflict(factories::id)
+                .do_update()
+                .set(factory)
+                .execute(connection)?;
+        }
 
-        diesel::insert_into(factories::dsl::factories)
-            .values(data)
-            .on_conflict(factories::id)
-            .do_update()
-            .set(data)
-            .execute(&mut connection)
-            .unwrap();
+        Ok(updated)
     }
 
-    pub async fn update_token(&self, data: &DatabaseToken) {
-        let mut connection: PgConnection = self.get_connection();
-
+    pub fn update_token(
+        connection: &mut PgConnection,
+        data: &DatabaseToken,
+    ) -> QueryResult<usize> {
         diesel::insert_into(tokens::dsl::tokens)
             .values(data)
             .on_conflict(tokens::id)
             .do_update()
             .set(data)
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_tokens(&self, data: &Vec<DatabaseToken>) {
-        let mut connection: PgConnection = self.get_connection();
-
+    pub fn update_tokens(
+        connection: &mut PgConnection,
+        data: &Vec<DatabaseToken>,
+    ) -> QueryResult<usize> {
         diesel::insert_into(tokens::dsl::tokens)
             .values(data)
             .on_conflict(tokens::id)
@@ -403,26 +991,29 @@ impl Database {
                 tokens::total_liquidity
                     .eq(excluded(tokens::total_liquidity)),
                 tokens::derived_eth.eq(excluded(tokens::derived_eth)),
+                tokens::price_route.eq(excluded(tokens::price_route)),
+                tokens::price_route_liquidity_eth
+                    .eq(excluded(tokens::price_route_liquidity_eth)),
             ))
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_pair(&self, data: &DatabasePair) {
-        let mut connection: PgConnection = self.get_connection();
-
+    pub fn update_pair(
+        connection: &mut PgConnection,
+        data: &DatabasePair,
+    ) -> QueryResult<usize> {
         diesel::insert_into(pairs::dsl::pairs)
             .values(data)
             .on_conflict(pairs::id)
             .do_update()
             .set(data)
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_pairs(&self, data: &Vec<DatabasePair>) {
-        let mut connection: PgConnection = self.get_connection();
-
+    pub fn update_pairs(
+        connection: &mut PgConnection,
+        data: &Vec<DatabasePair>,
+    ) -> QueryResult<usize> {
         diesel::insert_into(pairs::dsl::pairs)
             .values(data)
             .on_conflict(pairs::id)
@@ -451,26 +1042,27 @@ impl Database {
                     .eq(excluded(pairs::created_at_block_number)),
                 pairs::liquidity_provider_count
                     .eq(excluded(pairs::liquidity_provider_count)),
+                pairs::factory.eq(excluded(pairs::factory)),
             ))
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_burn(&self, data: &DatabaseBurn) {
-        let mut connection: PgConnection = self.get_connection();
-
+    pub fn update_burn(
+        connection: &mut PgConnection,
+        data: &DatabaseBurn,
+    ) -> QueryResult<usize> {
         diesel::insert_into(burns::dsl::burns)
             .values(data)
             .on_conflict(burns::id)
             .do_update()
             .set(data)
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_burns(&self, data: &Vec<DatabaseBurn>) {
-        let mut connection: PgConnection = self.get_connection();
-
+    pub fn update_burns(
+        connection: &mut PgConnection,
+        data: &Vec<DatabaseBurn>,
+    ) -> QueryResult<usize> {
         diesel::insert_into(burns::dsl::burns)
             .values(data)
             .on_conflict(burns::id)
@@ -491,25 +1083,25 @@ impl Database {
                 burns::fee_to.eq(excluded(burns::fee_to)),
                 burns::fee_liquidity.eq(excluded(burns::fee_liquidity)),
             ))
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_mint(&self, data: &DatabaseMint) {
-        let mut connection: PgConnection = self.get_connection();
-
+    pub fn update_mint(
+        connection: &mut PgConnection,
+        data: &DatabaseMint,
+    ) -> QueryResult<usize> {
         diesel::insert_into(mints::dsl::mints)
             .values(data)
             .on_conflict(mints::id)
             .do_update()
             .set(data)
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_mints(&self, data: &Vec<DatabaseMint>) {
-        let mut connection: PgConnection = self.get_connection();
-
+    pub fn update_mints(
+        connection: &mut PgConnection,
+        data: &Vec<DatabaseMint>,
+    ) -> QueryResult<usize> {
         diesel::insert_into(mints::dsl::mints)
             .values(data)
             .on_conflict(mints::id)
@@ -529,37 +1121,73 @@ impl Database {
                 mints::fee_to.eq(excluded(mints::fee_to)),
                 mints::fee_liquidity.eq(excluded(mints::fee_liquidity)),
             ))
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_bundle(&self, data: &DatabaseBundle) {
-        let mut connection: PgConnection = self.get_connection();
-
+    pub fn update_bundle(
+        connection: &mut PgConnection,
+        data: &DatabaseBundle,
+    ) -> QueryResult<usize> {
         diesel::insert_into(bundles::dsl::bundles)
             .values(data)
             .on_conflict(bundles::id)
             .do_update()
             .set(data)
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_swap(&self, data: &DatabaseSwap) {
-        let mut connection: PgConnection = self.get_connection();
+    pub fn update_events(
+        connection: &mut PgConnection,
+        data: &[DatabaseEvent],
+    ) -> QueryResult<usize> {
+        let mut inserted = 0;
+
+        // Busy contracts can emit more events in a batch than the bind
+        // parameters of a single insert allow.
+        for chunk in data.chunks(5_000) {
+            inserted += diesel::insert_into(events::dsl::events)
+                .values(chunk)
+                .on_conflict(events::id)
+                .do_nothing()
+                .execute(connection)?;
+        }
+
+        Ok(inserted)
+    }
 
+    pub fn update_eth_prices(
+        connection: &mut PgConnection,
+        data: &Vec<DatabaseEthPrice>,
+    ) -> QueryResult<usize> {
+        diesel::insert_into(eth_price_history::dsl::eth_price_history)
+            .values(data)
+            .on_conflict(eth_price_history::block_number)
+            .do_update()
+            .set((
+                eth_price_history::timestamp
+                    .eq(excluded(eth_price_history::timestamp)),
+                eth_price_history::eth_price
+                    .eq(excluded(eth_price_history::eth_price)),
+            ))
+            .execute(connection)
+    }
+
+    pub fn update_swap(
+        connection: &mut PgConnection,
+        data: &DatabaseSwap,
+    ) -> QueryResult<usize> {
         diesel::insert_into(swaps::dsl::swaps)
             .values(data)
             .on_conflict(swaps::id)
             .do_update()
             .set(data)
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_swaps(&self, data: &Vec<DatabaseSwap>) {
-        let mut connection: PgConnection = self.get_connection();
-
+    pub fn update_swaps(
+        connection: &mut PgConnection,
+        data: &Vec<DatabaseSwap>,
+    ) -> QueryResult<usize> {
         diesel::insert_into(swaps::dsl::swaps)
             .values(data)
             .on_conflict(swaps::id)
@@ -579,28 +1207,25 @@ impl Database {
                 swaps::log_index.eq(excluded(swaps::log_index)),
                 swaps::amount_usd.eq(excluded(swaps::amount_usd)),
             ))
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_transaction(&self, data: &DatabaseTransaction) {
-        let mut connection: PgConnection = self.get_connection();
-
+    pub fn update_transaction(
+        connection: &mut PgConnection,
+        data: &DatabaseTransaction,
+    ) -> QueryResult<usize> {
         diesel::insert_into(transactions::dsl::transactions)
             .values(data)
             .on_conflict(transactions::id)
             .do_update()
             .set(data)
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_transactions(
-        &self,
+    pub fn update_transactions(
+        connection: &mut PgConnection,
         data: &Vec<DatabaseTransaction>,
-    ) {
-        let mut connection: PgConnection = self.get_connection();
-
+    ) -> QueryResult<usize> {
         diesel::insert_into(transactions::dsl::transactions)
             .values(data)
             .on_conflict(transactions::id)
@@ -615,28 +1240,25 @@ impl Database {
                 transactions::swaps.eq(excluded(transactions::swaps)),
                 transactions::burns.eq(excluded(transactions::burns)),
             ))
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_dex_day_data(&self, data: &DatabaseDexDayData) {
-        let mut connection: PgConnection = self.get_connection();
-
+    pub fn update_dex_day_data(
+        connection: &mut PgConnection,
+        data: &DatabaseDexDayData,
+    ) -> QueryResult<usize> {
         diesel::insert_into(dex_day_data::dsl::dex_day_data)
             .values(data)
             .on_conflict(dex_day_data::id)
             .do_update()
             .set(data)
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_dexes_day_data(
-        &self,
+    pub fn update_dexes_day_data(
+        connection: &mut PgConnection,
         data: &Vec<DatabaseDexDayData>,
-    ) {
-        let mut connection: PgConnection = self.get_connection();
-
+    ) -> QueryResult<usize> {
         diesel::insert_into(dex_day_data::dsl::dex_day_data)
             .values(data)
             .on_conflict(dex_day_data::id)
@@ -661,28 +1283,25 @@ impl Database {
                 dex_day_data::tx_count
                     .eq(excluded(dex_day_data::tx_count)),
             ))
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_pair_day_data(&self, data: &DatabasePairDayData) {
-        let mut connection: PgConnection = self.get_connection();
-
+    pub fn update_pair_day_data(
+        connection: &mut PgConnection,
+        data: &DatabasePairDayData,
+    ) -> QueryResult<usize> {
         diesel::insert_into(pair_day_data::dsl::pair_day_data)
             .values(data)
             .on_conflict(pair_day_data::id)
             .do_update()
             .set(data)
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_pairs_day_data(
-        &self,
+    pub fn update_pairs_day_data(
+        connection: &mut PgConnection,
         data: &Vec<DatabasePairDayData>,
-    ) {
-        let mut connection: PgConnection = self.get_connection();
-
+    ) -> QueryResult<usize> {
         diesel::insert_into(pair_day_data::dsl::pair_day_data)
             .values(data)
             .on_conflict(pair_day_data::id)
@@ -711,31 +1330,25 @@ impl Database {
                 pair_day_data::daily_txns
                     .eq(excluded(pair_day_data::daily_txns)),
             ))
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_pair_hour_data(
-        &self,
+    pub fn update_pair_hour_data(
+        connection: &mut PgConnection,
         data: &DatabasePairHourData,
-    ) {
-        let mut connection: PgConnection = self.get_connection();
-
+    ) -> QueryResult<usize> {
         diesel::insert_into(pair_hour_data::dsl::pair_hour_data)
             .values(data)
             .on_conflict(pair_hour_data::id)
             .do_update()
             .set(data)
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_pairs_hour_data(
-        &self,
+    pub fn update_pairs_hour_data(
+        connection: &mut PgConnection,
         data: &Vec<DatabasePairHourData>,
-    ) {
-        let mut connection: PgConnection = self.get_connection();
-
+    ) -> QueryResult<usize> {
         diesel::insert_into(pair_hour_data::dsl::pair_hour_data)
             .values(data)
             .on_conflict(pair_hour_data::id)
@@ -762,31 +1375,25 @@ impl Database {
                 pair_hour_data::hourly_txns
                     .eq(excluded(pair_hour_data::hourly_txns)),
             ))
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_token_day_data(
-        &self,
+    pub fn update_token_day_data(
+        connection: &mut PgConnection,
         data: &DatabaseTokenDayData,
-    ) {
-        let mut connection: PgConnection = self.get_connection();
-
+    ) -> QueryResult<usize> {
         diesel::insert_into(token_day_data::dsl::token_day_data)
             .values(data)
             .on_conflict(token_day_data::id)
             .do_update()
             .set(data)
-            .execute(&mut connection)
-            .unwrap();
+            .execute(connection)
     }
 
-    pub async fn update_tokens_day_data(
-        &self,
+    pub fn update_tokens_day_data(
+        connection: &mut PgConnection,
         data: &Vec<DatabaseTokenDayData>,
-    ) {
-        let mut connection: PgConnection = self.get_connection();
-
+    ) -> QueryResult<usize> {
         diesel::insert_into(token_day_data::dsl::token_day_data)
             .values(data)
             .on_conflict(token_day_data::id)
@@ -812,18 +1419,224 @@ impl Database {
                 token_day_data::price_usd
                     .eq(excluded(token_day_data::price_usd)),
             ))
-            .execute(&mut connection)
+            .execute(connection)
+    }
+
+    pub async fn get_block(&self, number: i32) -> Option<DatabaseBlock> {
+        self.run(move |connection| {
+            blocks::dsl::blocks
+                .find(number)
+                .first::<DatabaseBlock>(connection)
+                .optional()
+                .unwrap()
+        })
+        .await
+    }
+
+    pub async fn get_blocks(
+        &self,
+        from_block: i32,
+        to_block: i32,
+    ) -> Vec<DatabaseBlock> {
+        self.run(move |connection| {
+            blocks::dsl::blocks
+                .filter(blocks::dsl::number.ge(from_block))
+                .filter(blocks::dsl::number.le(to_block))
+                .order(blocks::dsl::number.desc())
+                .load::<DatabaseBlock>(connection)
+                .unwrap()
+        })
+        .await
+    }
+
+    pub async fn get_blocks_by_number(
+        &self,
+        numbers: Vec<i32>,
+    ) -> Vec<DatabaseBlock> {
+        self.run(move |connection| {
+            blocks::dsl::blocks
+                .filter(blocks::dsl::number.eq_any(numbers))
+                .load::<DatabaseBlock>(connection)
+                .unwrap()
+        })
+        .await
+    }
+
+    pub fn update_block(
+        connection: &mut PgConnection,
+        data: &DatabaseBlock,
+    ) -> QueryResult<usize> {
+        diesel::insert_into(blocks::dsl::blocks)
+            .values(data)
+            .on_conflict(blocks::number)
+            .do_update()
+            .set(data)
+            .execute(connection)
+    }
+
+    pub fn update_blocks(
+        connection: &mut PgConnection,
+        data: &Vec<DatabaseBlock>,
+    ) -> QueryResult<usize> {
+        diesel::insert_into(blocks::dsl::blocks)
+            .values(data)
+            .on_conflict(blocks::number)
+            .do_update()
+            .set((
+                blocks::hash.eq(excluded(blocks::hash)),
+                blocks::parent_hash.eq(excluded(blocks::parent_hash)),
+                blocks::timestamp.eq(excluded(blocks::timestamp)),
+            ))
+            .execute(connection)
+    }
+
+    pub fn journal(
+        connection: &mut PgConnection,
+        first_block: i32,
+        last_block: i32,
+        table: &'static str,
+        ids: Vec<String>,
+    ) -> QueryResult<usize> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        sql_query(format!(
+            "INSERT INTO journal (first_block, last_block, entity, entity_id, data) \
+             SELECT $1, $2, '{table}', key, \
+             (SELECT to_jsonb(entity) FROM {table} entity WHERE entity.id = key) \
+             FROM unnest($3) key"
+        ))
+        .bind::<Integer, _>(first_block)
+        .bind::<Integer, _>(last_block)
+        .bind::<Array<Text>, _>(ids)
+        .execute(connection)
+    }
+
+    pub async fn prune_journal(&self, block: i32) {
+        self.run(move |connection| {
+            diesel::delete(
+                journal::dsl::journal
+                    .filter(journal::dsl::last_block.lt(block)),
+            )
+            .execute(connection)
             .unwrap();
+        })
+        .await
     }
 
-    pub async fn update_state(&self, last_indexed_block: i32) {
-        let mut connection: PgConnection = self.get_connection();
+    pub async fn rollback(
+        &self,
+        fork_block: i32,
+        handlers: Vec<Arc<dyn EventHandler>>,
+    ) -> i32 {
+        let last_block = self
+            .run(move |connection| {
+            connection
+                .transaction::<i32, diesel::result::Error, _>(|connection| {
+                    let orphaned = journal::dsl::journal
+                        .select((
+                            journal::dsl::id,
+                            journal::dsl::first_block,
+                            journal::dsl::last_block,
+                            journal::dsl::entity,
+                            journal::dsl::entity_id,
+                        ))
+                        .filter(journal::dsl::last_block.gt(fork_block))
+                        .load::<JournalEntry>(connection)?;
+
+                    let last_block = rollback_block(fork_block, &orphaned);
+                    let pre_images = pre_images(&orphaned);
+
+                    sql_query("SET CONSTRAINTS ALL DEFERRED")
+                        .execute(connection)?;
+
+                    for table in JOURNAL_TABLES.iter().rev() {
+                        sql_query(format!(
+                            "DELETE FROM {table} WHERE id IN \
+                             (SELECT entity_id FROM journal \
+                             WHERE entity = '{table}' AND last_block > $1)"
+                        ))
+                        .bind::<Integer, _>(fork_block)
+                        .execute(connection)?;
+                    }
+
+                    for table in JOURNAL_TABLES {
+                        sql_query(format!(
+                            "INSERT INTO {table} \
+                             SELECT (jsonb_populate_record(NULL::{table}, data)).* \
+                             FROM journal WHERE entity = '{table}' \
+                             AND id = ANY($1) AND data IS NOT NULL"
+                        ))
+                        .bind::<Array<Integer>, _>(&pre_images)
+                        .execute(connection)?;
+                    }
+
+                    diesel::delete(
+                        journal::dsl::journal
+                            .filter(journal::dsl::last_block.gt(fork_block)),
+                    )
+                    .execute(connection)?;
+
+                    diesel::delete(
+                        blocks::dsl::blocks
+                            .filter(blocks::dsl::number.gt(last_block)),
+                    )
+                    .execute(connection)?;
+
+                    diesel::delete(
+                        eth_price_history::dsl::eth_price_history.filter(
+                            eth_price_history::dsl::block_number
+                                .gt(last_block),
+                        ),
+                    )
+                    .execute(connection)?;
+
+                    diesel::delete(
+                        events::dsl::events
+                            .filter(events::dsl::block_number.gt(last_block)),
+                    )
+                    .execute(connection)?;
+
+                    for handler in handlers.iter() {
+                        handler.rollback(connection, last_block)?;
+                    }
+
+                    diesel::update(
+                        sync_state::dsl::sync_state
+                            .find(DatabaseKeys::State.as_str()),
+                    )
+                    .set(sync_state::dsl::last_block_indexed.eq(last_block))
+                    .execute(connection)?;
+
+                    Ok(last_block)
+                })
+                .unwrap()
+        })
+        .await;
+
+        // Rolled back entities may be cached with their orphaned state.
+        self.entities.lock().unwrap().clear();
+
+        let pair_graph = self
+            .run(|connection| {
+                Database::load_pair_graph(connection).unwrap()
+            })
+            .await;
+
+        *self.pair_graph.write().unwrap() = pair_graph;
+
+        last_block
+    }
 
+    pub fn update_state(
+        connection: &mut PgConnection,
+        last_indexed_block: i32,
+    ) -> QueryResult<usize> {
         diesel::update(
             sync_state::dsl::sync_state.find(DatabaseKeys::State.as_str()),
         )
         .set(sync_state::dsl::last_block_indexed.eq(last_indexed_block))
-        .execute(&mut connection)
-        .unwrap();
+        .execute(connection)
     }
 }
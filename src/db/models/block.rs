@@ -0,0 +1,24 @@
+use diesel::{AsChangeset, Insertable, Queryable};
+
+use crate::db::schema::blocks;
+
+#[derive(Queryable, Insertable, Debug, Clone, AsChangeset)]
+#[diesel(table_name = blocks)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DatabaseBlock {
+    pub number: i32,
+    pub hash: String,
+    pub parent_hash: String,
+    pub timestamp: i32,
+}
+
+impl DatabaseBlock {
+    pub fn new(
+        number: i32,
+        hash: String,
+        parent_hash: String,
+        timestamp: i32,
+    ) -> Self {
+        Self { number, hash, parent_hash, timestamp }
+    }
+}
@@ -1,5 +1,6 @@
 use bigdecimal::BigDecimal;
 use diesel::{AsChangeset, Insertable, Queryable};
+use serde_json::Value;
 
 use crate::{
     db::{schema::bundles, DatabaseKeys},
@@ -12,6 +13,7 @@ use crate::{
 pub struct DatabaseBundle {
     pub id: String,
     pub eth_price: BigDecimal,
+    pub eth_price_sources: Value,
 }
 
 impl Default for DatabaseBundle {
@@ -25,6 +27,7 @@ impl DatabaseBundle {
         Self {
             id: DatabaseKeys::Bundle.as_str().to_owned(),
             eth_price: zero_bd(),
+            eth_price_sources: Value::Array(Vec::new()),
         }
     }
 }
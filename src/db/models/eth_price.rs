@@ -0,0 +1,23 @@
+use bigdecimal::BigDecimal;
+use diesel::{AsChangeset, Insertable, Queryable};
+
+use crate::db::schema::eth_price_history;
+
+#[derive(Queryable, Insertable, Debug, Clone, AsChangeset)]
+#[diesel(table_name = eth_price_history)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DatabaseEthPrice {
+    pub block_number: i32,
+    pub timestamp: i32,
+    pub eth_price: BigDecimal,
+}
+
+impl DatabaseEthPrice {
+    pub fn new(
+        block_number: i32,
+        timestamp: i32,
+        eth_price: BigDecimal,
+    ) -> Self {
+        Self { block_number, timestamp, eth_price }
+    }
+}
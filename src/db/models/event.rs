@@ -0,0 +1,45 @@
+use diesel::{AsChangeset, Insertable, Queryable};
+use serde_json::Value;
+
+use crate::db::schema::events;
+
+#[derive(Queryable, Insertable, Debug, Clone, AsChangeset)]
+#[diesel(table_name = events)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DatabaseEvent {
+    pub id: String,
+    pub transaction: String,
+    pub block_number: i32,
+    pub timestamp: i32,
+    pub log_index: i32,
+    pub address: String,
+    pub contract: String,
+    pub event: String,
+    pub args: Value,
+}
+
+#[allow(clippy::too_many_arguments)]
+impl DatabaseEvent {
+    pub fn new(
+        transaction: String,
+        block_number: i32,
+        timestamp: i32,
+        log_index: i32,
+        address: String,
+        contract: String,
+        event: String,
+        args: Value,
+    ) -> Self {
+        Self {
+            id: format!("{}-{}", transaction, log_index),
+            transaction,
+            block_number,
+            timestamp,
+            log_index,
+            address,
+            contract,
+            event,
+            args,
+        }
+    }
+}
@@ -1,10 +1,7 @@
 use bigdecimal::BigDecimal;
 use diesel::{AsChangeset, Insertable, Queryable};
 
-use crate::{
-    db::{schema::factories, DatabaseKeys},
-    utils::format::zero_bd,
-};
+use crate::{db::schema::factories, utils::format::zero_bd};
 
 #[derive(Queryable, Insertable, Debug, Clone, AsChangeset)]
 #[diesel(table_name = factories)]
@@ -21,16 +18,10 @@ pub struct DatabaseFactory {
     pub tx_count: i32,
 }
 
-impl Default for DatabaseFactory {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 impl DatabaseFactory {
-    pub fn new() -> Self {
+    pub fn new(id: String) -> Self {
         Self {
-            id: DatabaseKeys::Factory.as_str().to_owned(),
+            id,
             pair_count: 0,
             pairs: vec![],
             total_volume_usd: zero_bd(),
@@ -1,6 +1,9 @@
+pub mod block;
 pub mod bundle;
 pub mod burn;
 pub mod data;
+pub mod eth_price;
+pub mod event;
 pub mod factory;
 pub mod mint;
 pub mod pair;
@@ -30,11 +30,13 @@ pub struct DatabasePair {
     pub created_at_timestamp: i32,
     pub created_at_block_number: i32,
     pub liquidity_provider_count: i32,
+    pub factory: String,
 }
 
 impl DatabasePair {
     pub fn new(
         event: Log<PairCreated>,
+        factory: String,
         created_at_timestamp: i32,
         created_at_block_number: i32,
     ) -> Self {
@@ -58,6 +60,7 @@ impl DatabasePair {
             created_at_timestamp,
             created_at_block_number,
             liquidity_provider_count: 0,
+            factory,
         }
     }
 }
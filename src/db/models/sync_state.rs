@@ -8,19 +8,15 @@ use crate::db::{schema::sync_state, DatabaseKeys};
 pub struct DatabaseSyncState {
     pub id: String,
     pub last_block_indexed: i32,
-}
-
-impl Default for DatabaseSyncState {
-    fn default() -> Self {
-        Self::new()
-    }
+    pub mode: String,
 }
 
 impl DatabaseSyncState {
-    pub fn new() -> Self {
+    pub fn new(mode: String) -> Self {
         Self {
             id: DatabaseKeys::State.as_str().to_owned(),
             last_block_indexed: 0,
+            mode,
         }
     }
 }
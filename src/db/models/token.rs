@@ -18,6 +18,8 @@ pub struct DatabaseToken {
     pub tx_count: i32,
     pub total_liquidity: BigDecimal,
     pub derived_eth: BigDecimal,
+    pub price_route: String,
+    pub price_route_liquidity_eth: BigDecimal,
 }
 
 impl DatabaseToken {
@@ -40,6 +42,8 @@ impl DatabaseToken {
             tx_count: 0,
             total_liquidity: zero_bd(),
             derived_eth: zero_bd(),
+            price_route: String::new(),
+            price_route_liquidity_eth: zero_bd(),
         }
     }
 }
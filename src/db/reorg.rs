@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use diesel::Queryable;
+
+use super::models::block::DatabaseBlock;
+
+// A journal row holds the state of an entity before the batch of blocks
+// `first_block..=last_block` changed it, the state itself stays in the
+// database.
+#[derive(Queryable, Debug, Clone)]
+pub struct JournalEntry {
+    pub id: i32,
+    pub first_block: i32,
+    pub last_block: i32,
+    pub entity: String,
+    pub entity_id: String,
+}
+
+// Returns the highest stored block that is still part of the canonical
+// chain. Only the blocks at the chunk boundaries are stored, so the fork may
+// be anywhere after it.
+pub fn find_fork_block(
+    stored: &[DatabaseBlock],
+    canonical: &[DatabaseBlock],
+) -> Option<i32> {
+    let canonical: HashMap<i32, &str> = canonical
+        .iter()
+        .map(|block| (block.number, block.hash.as_str()))
+        .collect();
+
+    stored
+        .iter()
+        .filter(|block| {
+            canonical.get(&block.number) == Some(&block.hash.as_str())
+        })
+        .map(|block| block.number)
+        .max()
+}
+
+// Returns the last block kept by a rollback to the fork block, given the
+// journal rows of the batches ending after it. A batch that straddles the
+// fork point is undone entirely.
+pub fn rollback_block(fork_block: i32, journal: &[JournalEntry]) -> i32 {
+    match journal.iter().map(|entry| entry.first_block).min() {
+        Some(block) => std::cmp::min(fork_block, block - 1),
+        None => fork_block,
+    }
+}
+
+// Returns the journal rows to restore, the pre-image of every entity is the
+// one written by the earliest undone batch.
+pub fn pre_images(journal: &[JournalEntry]) -> Vec<i32> {
+    let mut earliest: HashMap<(&str, &str), &JournalEntry> =
+        HashMap::new();
+
+    for entry in journal {
+        earliest
+            .entry((entry.entity.as_str(), entry.entity_id.as_str()))
+            .and_modify(|current| {
+                if (entry.first_block, entry.id)
+                    < (current.first_block, current.id)
+                {
+                    *current = entry;
+                }
+            })
+            .or_insert(entry);
+    }
+
+    let mut ids: Vec<i32> =
+        earliest.values().map(|entry| entry.id).collect();
+
+    ids.sort();
+
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(number: i32, hash: &str) -> DatabaseBlock {
+        DatabaseBlock::new(number, hash.to_owned(), String::new(), 0)
+    }
+
+    fn entry(
+        id: i32,
+        blocks: (i32, i32),
+        entity: &str,
+        entity_id: &str,
+    ) -> JournalEntry {
+        JournalEntry {
+            id,
+            first_block: blocks.0,
+            last_block: blocks.1,
+            entity: entity.to_owned(),
+            entity_id: entity_id.to_owned(),
+        }
+    }
+
+    #[test]
+    fn finds_the_highest_canonical_block() {
+        let stored =
+            [block(199, "0xb"), block(149, "0xa"), block(99, "0x9")];
+        let canonical =
+            [block(199, "0xc"), block(149, "0xa"), block(99, "0x9")];
+
+        assert_eq!(find_fork_block(&stored, &canonical), Some(149));
+    }
+
+    #[test]
+    fn finds_no_fork_block_when_every_block_changed() {
+        let stored = [block(199, "0xb"), block(149, "0xa")];
+        let canonical = [block(199, "0xd"), block(149, "0xc")];
+
+        assert_eq!(find_fork_block(&stored, &canonical), None);
+        assert_eq!(find_fork_block(&stored, &[]), None);
+    }
+
+    #[test]
+    fn rolls_back_to_the_fork_block_between_batches() {
+        let journal = [
+            entry(1, (150, 199), "pairs", "0x1"),
+            entry(2, (200, 249), "pairs", "0x1"),
+        ];
+
+        assert_eq!(rollback_block(149, &journal), 149);
+        assert_eq!(rollback_block(149, &[]), 149);
+    }
+
+    #[test]
+    fn rolls_back_a_batch_straddling_the_fork_entirely() {
+        // Blocks 150 to 199 were indexed in one chunk and the fork is in the
+        // middle of it, the chunk is synced again from its first block.
+        let journal = [
+            entry(1, (150, 199), "pairs", "0x1"),
+            entry(2, (150, 199), "tokens", "0xa"),
+            entry(3, (200, 200), "pairs", "0x1"),
+        ];
+
+        assert_eq!(rollback_block(170, &journal), 149);
+    }
+
+    #[test]
+    fn restores_the_earliest_pre_image_of_every_entity() {
+        let journal = [
+            entry(1, (150, 199), "pairs", "0x1"),
+            entry(2, (150, 199), "tokens", "0x1"),
+            entry(3, (200, 200), "pairs", "0x1"),
+            entry(4, (200, 200), "pairs", "0x2"),
+            entry(5, (201, 201), "tokens", "0x1"),
+            entry(6, (201, 201), "tokens", "0xa"),
+        ];
+
+        assert_eq!(pre_images(&journal), vec![1, 2, 4, 6]);
+        assert!(pre_images(&[]).is_empty());
+    }
+
+    #[test]
+    fn rolls_back_a_reorg_across_a_chunk_boundary() {
+        // Chunks ended at blocks 149 and 199, the fork is at block 180.
+        let stored = [block(199, "0xb"), block(149, "0xa")];
+        let canonical = [block(199, "0xc"), block(149, "0xa")];
+
+        let fork_block = find_fork_block(&stored, &canonical).unwrap();
+
+        let journal = [
+            entry(1, (150, 199), "pairs", "0x1"),
+            entry(2, (200, 210), "pairs", "0x1"),
+            entry(3, (200, 210), "tokens", "0xa"),
+        ];
+
+        assert_eq!(fork_block, 149);
+        assert_eq!(rollback_block(fork_block, &journal), 149);
+        assert_eq!(pre_images(&journal), vec![1, 3]);
+    }
+}
@@ -1,9 +1,19 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    blocks (number) {
+        number -> Int4,
+        hash -> Text,
+        parent_hash -> Text,
+        timestamp -> Int4,
+    }
+}
+
 diesel::table! {
     bundles (id) {
         id -> Text,
         eth_price -> Numeric,
+        eth_price_sources -> Jsonb,
     }
 }
 
@@ -41,6 +51,28 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    eth_price_history (block_number) {
+        block_number -> Int4,
+        timestamp -> Int4,
+        eth_price -> Numeric,
+    }
+}
+
+diesel::table! {
+    events (id) {
+        id -> Text,
+        transaction -> Text,
+        block_number -> Int4,
+        timestamp -> Int4,
+        log_index -> Int4,
+        address -> Text,
+        contract -> Text,
+        event -> Text,
+        args -> Jsonb,
+    }
+}
+
 diesel::table! {
     factories (id) {
         id -> Text,
@@ -55,6 +87,17 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    journal (id) {
+        id -> Int4,
+        first_block -> Int4,
+        last_block -> Int4,
+        entity -> Text,
+        entity_id -> Text,
+        data -> Nullable<Jsonb>,
+    }
+}
+
 diesel::table! {
     mints (id) {
         id -> Text,
@@ -128,6 +171,7 @@ diesel::table! {
         created_at_timestamp -> Int4,
         created_at_block_number -> Int4,
         liquidity_provider_count -> Int4,
+        factory -> Text,
     }
 }
 
@@ -153,6 +197,7 @@ diesel::table! {
     sync_state (id) {
         id -> Text,
         last_block_indexed -> Int4,
+        mode -> Text,
     }
 }
 
@@ -185,6 +230,8 @@ diesel::table! {
         tx_count -> Int4,
         total_liquidity -> Numeric,
         derived_eth -> Numeric,
+        price_route -> Text,
+        price_route_liquidity_eth -> Numeric,
     }
 }
 
@@ -204,10 +251,14 @@ diesel::joinable!(pair_hour_data -> pairs (pair));
 diesel::joinable!(token_day_data -> tokens (token));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    blocks,
     bundles,
     burns,
     dex_day_data,
+    eth_price_history,
+    events,
     factories,
+    journal,
     mints,
     pair_day_data,
     pair_hour_data,
@@ -0,0 +1,54 @@
+use std::collections::HashSet;
+
+use alloy::rpc::types::Log;
+use log::debug;
+
+use crate::{
+    db::{Database, StorageCache},
+    rpc::{error::RpcError, Rpc},
+};
+
+pub async fn handle_block_timestamps(
+    logs: &[Log],
+    db: &Database,
+    rpc: &Rpc,
+    cache: &mut StorageCache,
+) -> Result<(), RpcError> {
+    let missing_blocks: HashSet<i32> = logs
+        .iter()
+        .filter(|log| log.block_timestamp.is_none())
+        .map(|log| log.block_number.unwrap() as i32)
+        .filter(|block_number| !cache.blocks.contains_key(block_number))
+        .collect();
+
+    if missing_blocks.is_empty() {
+        return Ok(());
+    }
+
+    let stored_blocks = db
+        .get_blocks_by_number(missing_blocks.iter().copied().collect())
+        .await;
+
+    for block in stored_blocks {
+        cache.blocks.insert(block.number, block);
+    }
+
+    let missing_blocks: Vec<i32> = missing_blocks
+        .into_iter()
+        .filter(|block_number| !cache.blocks.contains_key(block_number))
+        .collect();
+
+    if missing_blocks.is_empty() {
+        return Ok(());
+    }
+
+    let blocks = rpc.get_blocks(&missing_blocks).await?;
+
+    debug!(target: db.chain.name, "Fetched {} block headers for log timestamps", blocks.len());
+
+    for block in blocks {
+        cache.blocks.insert(block.number, block);
+    }
+
+    Ok(())
+}
@@ -1,13 +1,18 @@
-use alloy::{rpc::types::Log, sol, sol_types::SolEvent};
+use alloy::{primitives::B256, rpc::types::Log, sol, sol_types::SolEvent};
+use async_trait::async_trait;
 
 use crate::{
+    configs::Config,
     db::{Database, StorageCache},
     utils::format::{convert_token_to_decimal, parse_u256},
 };
 
-use super::utils::{
-    update_dex_day_data, update_pair_day_data, update_pair_hour_data,
-    update_token_day_data,
+use super::{
+    registry::{EventHandler, HandlerError},
+    utils::{
+        get_pair_factory, update_dex_day_data, update_pair_day_data,
+        update_pair_hour_data, update_token_day_data,
+    },
 };
 
 sol! {
@@ -19,7 +24,7 @@ pub async fn handle_burn(
     timestamp: i32,
     db: &Database,
     cache: &mut StorageCache,
-) {
+) -> Result<(), HandlerError> {
     let event = Burn::decode_log(&log.inner, true).unwrap();
     let sender_address = event.sender.to_string().to_lowercase();
     let transaction_hash = log.transaction_hash.unwrap().to_string();
@@ -28,7 +33,7 @@ pub async fn handle_burn(
         Some(transaction) => transaction.to_owned(),
         None => match db.get_transaction(&transaction_hash).await {
             Some(tx) => tx,
-            None => return,
+            None => return Ok(()),
         },
     };
 
@@ -39,7 +44,7 @@ pub async fn handle_burn(
         Some(transaction) => transaction.to_owned(),
         None => match db.get_burn(burn_id).await {
             Some(burn) => burn,
-            None => return,
+            None => return Ok(()),
         },
     };
 
@@ -57,7 +62,7 @@ pub async fn handle_burn(
         Some(token) => token.to_owned(),
         None => match db.get_token(&token0_address).await {
             Some(token) => token,
-            None => return,
+            None => return Ok(()),
         },
     };
 
@@ -65,7 +70,7 @@ pub async fn handle_burn(
         Some(token) => token.to_owned(),
         None => match db.get_token(&token1_address).await {
             Some(token) => token,
-            None => return,
+            None => return Ok(()),
         },
     };
 
@@ -87,7 +92,7 @@ pub async fn handle_burn(
             * cache.bundle.eth_price.clone();
 
     pair.tx_count += 1;
-    cache.factory.tx_count += 1;
+    get_pair_factory(&pair, cache)?.tx_count += 1;
 
     burn.sender = sender_address;
     burn.amount0 = token0_amount;
@@ -105,4 +110,30 @@ pub async fn handle_burn(
     update_dex_day_data(db, timestamp, cache).await;
     update_token_day_data(&token0, timestamp, db, cache).await;
     update_token_day_data(&token1, timestamp, db, cache).await;
+
+    Ok(())
+}
+
+pub struct BurnHandler;
+
+#[async_trait]
+impl EventHandler for BurnHandler {
+    fn name(&self) -> &'static str {
+        "burn"
+    }
+
+    fn topics(&self) -> Vec<B256> {
+        vec![Burn::SIGNATURE_HASH]
+    }
+
+    async fn handle(
+        &self,
+        log: &Log,
+        db: &Database,
+        _config: &Config,
+        cache: &mut StorageCache,
+    ) -> Result<(), HandlerError> {
+        handle_burn(log.clone(), cache.block_timestamp(log), db, cache)
+            .await
+    }
 }
@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+
+use alloy::{
+    dyn_abi::{DynSolValue, EventExt},
+    hex,
+    json_abi::{Event, JsonAbi, Param},
+    primitives::{Address, B256},
+    rpc::types::Log,
+};
+use async_trait::async_trait;
+use log::warn;
+use serde_json::{Map, Value};
+
+use crate::{
+    chains::Chain,
+    configs::Config,
+    db::{models::event::DatabaseEvent, Database, StorageCache},
+};
+
+use super::registry::{EventHandler, HandlerError};
+
+struct ContractEvents {
+    name: &'static str,
+    events: HashMap<B256, Event>,
+}
+
+// Stores the events of the chain contracts decoded with their JSON ABI.
+pub struct EventsHandler {
+    contracts: HashMap<Address, ContractEvents>,
+}
+
+impl EventsHandler {
+    pub fn new(chain: &Chain) -> Self {
+        let mut contracts = HashMap::new();
+
+        for contract in chain.contracts {
+            let abi: JsonAbi = serde_json::from_str(contract.abi).unwrap();
+
+            let events = abi
+                .events()
+                .filter(|event| !event.anonymous)
+                .map(|event| (event.selector(), event.clone()))
+                .collect();
+
+            contracts.insert(
+                contract.address.parse::<Address>().unwrap(),
+                ContractEvents { name: contract.name, events },
+            );
+        }
+
+        Self { contracts }
+    }
+}
+
+#[async_trait]
+impl EventHandler for EventsHandler {
+    fn name(&self) -> &'static str {
+        "contract"
+    }
+
+    fn topics(&self) -> Vec<B256> {
+        let mut topics: Vec<B256> = self
+            .contracts
+            .values()
+            .flat_map(|contract| contract.events.keys().copied())
+            .collect();
+
+        topics.sort();
+        topics.dedup();
+
+        topics
+    }
+
+    fn addresses(&self, _config: &Config) -> Vec<Address> {
+        self.contracts.keys().copied().collect()
+    }
+
+    async fn handle(
+        &self,
+        log: &Log,
+        _db: &Database,
+        config: &Config,
+        cache: &mut StorageCache,
+    ) -> Result<(), HandlerError> {
+        let contract = match self.contracts.get(&log.address()) {
+            Some(contract) => contract,
+            None => return Ok(()),
+        };
+
+        // The contracts are fetched together, so a topic may belong to the
+        // ABI of another contract.
+        let event = match log
+            .topic0()
+            .and_then(|topic| contract.events.get(topic))
+        {
+            Some(event) => event,
+            None => return Ok(()),
+        };
+
+        let decoded = match event.decode_log(log.data(), true) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                warn!(target: config.chain.name, "Unable to decode {} event of {} in transaction {} ({})",
+                    event.name, contract.name, log.transaction_hash.unwrap(), err
+                );
+
+                return Ok(());
+            }
+        };
+
+        let mut indexed = decoded.indexed.iter();
+        let mut body = decoded.body.iter();
+
+        let mut args = Map::new();
+
+        for (index, input) in event.inputs.iter().enumerate() {
+            let value =
+                if input.indexed { indexed.next() } else { body.next() };
+
+            let name = if input.name.is_empty() {
+                index.to_string()
+            } else {
+                input.name.clone()
+            };
+
+            args.insert(
+                name,
+                value_to_json(value.unwrap(), &input.components),
+            );
+        }
+
+        let event = DatabaseEvent::new(
+            log.transaction_hash.unwrap().to_string(),
+            log.block_number.unwrap() as i32,
+            cache.block_timestamp(log),
+            log.log_index.unwrap() as i32,
+            log.address().to_string().to_lowercase(),
+            contract.name.to_owned(),
+            event.name.clone(),
+            Value::Object(args),
+        );
+
+        cache.events.push(event);
+
+        Ok(())
+    }
+}
+
+// Integers are stored as strings to keep their precision, tuples with named
+// components as objects.
+fn value_to_json(value: &DynSolValue, components: &[Param]) -> Value {
+    match value {
+        DynSolValue::Bool(value) => Value::Bool(*value),
+        DynSolValue::Int(value, _) => Value::String(value.to_string()),
+        DynSolValue::Uint(value, _) => Value::String(value.to_string()),
+        DynSolValue::FixedBytes(value, size) => {
+            Value::String(hex::encode_prefixed(&value[..*size]))
+        }
+        DynSolValue::Address(value) => {
+            Value::String(value.to_string().to_lowercase())
+        }
+        DynSolValue::Function(value) => {
+            Value::String(hex::encode_prefixed(value))
+        }
+        DynSolValue::Bytes(value) => {
+            Value::String(hex::encode_prefixed(value))
+        }
+        DynSolValue::String(value) => Value::String(value.clone()),
+        DynSolValue::Array(values) | DynSolValue::FixedArray(values) => {
+            Value::Array(
+                values
+                    .iter()
+                    .map(|value| value_to_json(value, components))
+                    .collect(),
+            )
+        }
+        DynSolValue::Tuple(values) => {
+            if components.len() == values.len()
+                && components.iter().all(|param| !param.name.is_empty())
+            {
+                Value::Object(
+                    components
+                        .iter()
+                        .zip(values)
+                        .map(|(param, value)| {
+                            (
+                                param.name.clone(),
+                                value_to_json(value, &param.components),
+                            )
+                        })
+                        .collect(),
+                )
+            } else {
+                Value::Array(
+                    values
+                        .iter()
+                        .enumerate()
+                        .map(|(index, value)| {
+                            let components = match components.get(index) {
+                                Some(param) => param.components.as_slice(),
+                                None => &[],
+                            };
+
+                            value_to_json(value, components)
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{I256, U256};
+    use serde_json::json;
+
+    use super::*;
+
+    fn param(name: &str, ty: &str) -> Param {
+        Param {
+            ty: ty.to_owned(),
+            name: name.to_owned(),
+            components: Vec::new(),
+            internal_type: None,
+        }
+    }
+
+    #[test]
+    fn stores_integers_as_strings() {
+        assert_eq!(
+            value_to_json(&DynSolValue::Uint(U256::MAX, 256), &[]),
+            json!(U256::MAX.to_string())
+        );
+        assert_eq!(
+            value_to_json(&DynSolValue::Int(I256::MINUS_ONE, 256), &[]),
+            json!("-1")
+        );
+    }
+
+    #[test]
+    fn stores_addresses_and_bytes_as_hex() {
+        let address = Address::repeat_byte(0xab);
+
+        assert_eq!(
+            value_to_json(&DynSolValue::Address(address), &[]),
+            json!(format!("0x{}", "ab".repeat(20)))
+        );
+        assert_eq!(
+            value_to_json(
+                &DynSolValue::FixedBytes(B256::repeat_byte(1), 4),
+                &[]
+            ),
+            json!("0x01010101")
+        );
+        assert_eq!(
+            value_to_json(&DynSolValue::Bytes(vec![0xde, 0xad]), &[]),
+            json!("0xdead")
+        );
+    }
+
+    #[test]
+    fn stores_arrays_element_by_element() {
+        let values = DynSolValue::Array(vec![
+            DynSolValue::Bool(true),
+            DynSolValue::Bool(false),
+        ]);
+
+        assert_eq!(value_to_json(&values, &[]), json!([true, false]));
+    }
+
+    #[test]
+    fn stores_tuples_with_named_components_as_objects() {
+        let tuple = DynSolValue::Tuple(vec![
+            DynSolValue::String("taya".to_owned()),
+            DynSolValue::Uint(U256::from(7), 8),
+        ]);
+
+        assert_eq!(
+            value_to_json(
+                &tuple,
+                &[param("name", "string"), param("count", "uint8")]
+            ),
+            json!({ "name": "taya", "count": "7" })
+        );
+    }
+
+    #[test]
+    fn stores_tuples_with_unnamed_components_as_arrays() {
+        let tuple = DynSolValue::Tuple(vec![
+            DynSolValue::String("taya".to_owned()),
+            DynSolValue::Uint(U256::from(7), 8),
+        ]);
+
+        assert_eq!(
+            value_to_json(
+                &tuple,
+                &[param("name", "string"), param("", "uint8")]
+            ),
+            json!(["taya", "7"])
+        );
+        assert_eq!(value_to_json(&tuple, &[]), json!(["taya", "7"]));
+    }
+
+    #[test]
+    fn stores_nested_tuples_with_their_components() {
+        let mut inner = param("inner", "tuple");
+        inner.components = vec![param("amount", "uint256")];
+
+        let tuple = DynSolValue::Tuple(vec![DynSolValue::Tuple(vec![
+            DynSolValue::Uint(U256::from(1), 256),
+        ])]);
+
+        assert_eq!(
+            value_to_json(&tuple, &[inner]),
+            json!({ "inner": { "amount": "1" } })
+        );
+    }
+}
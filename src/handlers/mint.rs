@@ -1,13 +1,18 @@
-use alloy::{rpc::types::Log, sol, sol_types::SolEvent};
+use alloy::{primitives::B256, rpc::types::Log, sol, sol_types::SolEvent};
+use async_trait::async_trait;
 
 use crate::{
+    configs::Config,
     db::{Database, StorageCache},
     utils::format::{convert_token_to_decimal, parse_u256},
 };
 
-use super::utils::{
-    update_dex_day_data, update_pair_day_data, update_pair_hour_data,
-    update_token_day_data,
+use super::{
+    registry::{EventHandler, HandlerError},
+    utils::{
+        get_pair_factory, update_dex_day_data, update_pair_day_data,
+        update_pair_hour_data, update_token_day_data,
+    },
 };
 
 sol! {
@@ -19,7 +24,7 @@ pub async fn handle_mint(
     timestamp: i32,
     db: &Database,
     cache: &mut StorageCache,
-) {
+) -> Result<(), HandlerError> {
     let event = Mint::decode_log(&log.inner, true).unwrap();
     let transaction_hash = log.transaction_hash.unwrap().to_string();
 
@@ -27,7 +32,7 @@ pub async fn handle_mint(
         Some(transaction) => transaction.to_owned(),
         None => match db.get_transaction(&transaction_hash).await {
             Some(tx) => tx,
-            None => return,
+            None => return Ok(()),
         },
     };
 
@@ -38,7 +43,7 @@ pub async fn handle_mint(
         Some(mint) => mint.to_owned(),
         None => match db.get_mint(mint_id).await {
             Some(mint) => mint,
-            None => return,
+            None => return Ok(()),
         },
     };
 
@@ -56,7 +61,7 @@ pub async fn handle_mint(
         Some(token) => token.to_owned(),
         None => match db.get_token(&token0_address).await {
             Some(token) => token,
-            None => return,
+            None => return Ok(()),
         },
     };
 
@@ -64,7 +69,7 @@ pub async fn handle_mint(
         Some(token) => token.to_owned(),
         None => match db.get_token(&token1_address).await {
             Some(token) => token,
-            None => return,
+            None => return Ok(()),
         },
     };
 
@@ -87,7 +92,7 @@ pub async fn handle_mint(
             * cache.bundle.eth_price.clone();
 
     pair.tx_count += 1;
-    cache.factory.tx_count += 1;
+    get_pair_factory(&pair, cache)?.tx_count += 1;
 
     mint.sender = event.sender.to_string().to_lowercase();
     mint.amount0 = token0_amount;
@@ -105,4 +110,30 @@ pub async fn handle_mint(
     update_dex_day_data(db, timestamp, cache).await;
     update_token_day_data(&token0, timestamp, db, cache).await;
     update_token_day_data(&token1, timestamp, db, cache).await;
+
+    Ok(())
+}
+
+pub struct MintHandler;
+
+#[async_trait]
+impl EventHandler for MintHandler {
+    fn name(&self) -> &'static str {
+        "mint"
+    }
+
+    fn topics(&self) -> Vec<B256> {
+        vec![Mint::SIGNATURE_HASH]
+    }
+
+    async fn handle(
+        &self,
+        log: &Log,
+        db: &Database,
+        _config: &Config,
+        cache: &mut StorageCache,
+    ) -> Result<(), HandlerError> {
+        handle_mint(log.clone(), cache.block_timestamp(log), db, cache)
+            .await
+    }
 }
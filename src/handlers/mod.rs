@@ -1,6 +1,9 @@
+pub mod blocks;
 pub mod burn;
+pub mod events;
 pub mod mint;
 pub mod pairs;
+pub mod registry;
 pub mod swap;
 pub mod sync;
 pub mod transfer;
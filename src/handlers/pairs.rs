@@ -4,16 +4,21 @@ use log::info;
 use crate::{
     db::{
         models::{pair::DatabasePair, token::DatabaseToken},
-        Database,
+        Database, StorageCache,
     },
-    rpc::Rpc,
+    rpc::{error::RpcError, Rpc},
 };
 
 sol! {
     event PairCreated(address indexed token0, address indexed token1, address pair, uint);
 }
 
-pub async fn handle_pairs(pairs: Vec<Log>, db: &Database, rpc: &Rpc) {
+pub async fn handle_pairs(
+    pairs: Vec<Log>,
+    db: &Database,
+    rpc: &Rpc,
+    cache: &mut StorageCache,
+) -> Result<(), RpcError> {
     let mut count_tokens = 0;
 
     let count_pairs = pairs.len();
@@ -26,55 +31,84 @@ pub async fn handle_pairs(pairs: Vec<Log>, db: &Database, rpc: &Rpc) {
         let pair_address = event.pair.to_string().to_lowercase();
 
         let (token0, token1) = tokio::join!(
-            db.get_token(&token0_address),
-            db.get_token(&token1_address)
+            async {
+                match cache.tokens.get(&token0_address) {
+                    Some(token) => Some(token.to_owned()),
+                    None => db.get_token(&token0_address).await,
+                }
+            },
+            async {
+                match cache.tokens.get(&token1_address) {
+                    Some(token) => Some(token.to_owned()),
+                    None => db.get_token(&token1_address).await,
+                }
+            }
         );
 
-        let mut factory = db.get_factory().await;
+        let factory = db
+            .chain
+            .factory_by_address(&log.address().to_string().to_lowercase())
+            .unwrap();
 
-        factory.pair_count += 1;
-        factory.pairs.push(Some(pair_address));
+        let factory_data = cache.factories.get_mut(factory.name).unwrap();
+
+        factory_data.pair_count += 1;
+        factory_data.pairs.push(Some(pair_address.clone()));
+
+        cache.pair_graph.insert(
+            &token0_address,
+            &token1_address,
+            factory.name,
+            &pair_address,
+        );
 
         if token0.is_none() {
             let (name, symbol, total_supply, decimals) =
-                rpc.get_token_information(token0_address.clone()).await;
+                rpc.get_token_information(token0_address.clone()).await?;
 
             let token = DatabaseToken::new(
-                token0_address,
+                token0_address.clone(),
                 symbol,
                 name,
                 decimals,
                 total_supply,
             );
 
-            db.update_token(&token).await;
+            cache.tokens.insert(token0_address, token);
             count_tokens += 1;
         }
 
         if token1.is_none() {
             let (name, symbol, total_supply, decimals) =
-                rpc.get_token_information(token1_address.clone()).await;
+                rpc.get_token_information(token1_address.clone()).await?;
 
             let token = DatabaseToken::new(
-                token1_address,
+                token1_address.clone(),
                 symbol,
                 name,
                 decimals,
                 total_supply,
             );
 
-            db.update_token(&token).await;
+            cache.tokens.insert(token1_address, token);
 
             count_tokens += 1;
         }
 
         let block_number = log.block_number.unwrap() as i32;
-        let block_timestamp = log.block_timestamp.unwrap() as i32;
+        let block_timestamp = cache.block_timestamp(&log);
 
-        let pair = DatabasePair::new(event, block_timestamp, block_number);
+        let pair = DatabasePair::new(
+            event,
+            factory.name.to_owned(),
+            block_timestamp,
+            block_number,
+        );
 
-        tokio::join!(db.update_factory(&factory), db.update_pair(&pair));
+        cache.pairs.insert(pair_address, pair);
     }
 
-    info!("Stored {} pairs and {} tokens", count_pairs, count_tokens);
+    info!(target: db.chain.name, "Stored {} pairs and {} tokens", count_pairs, count_tokens);
+
+    Ok(())
 }
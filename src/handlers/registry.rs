@@ -0,0 +1,190 @@
+use std::{collections::HashMap, error::Error, sync::Arc};
+
+use alloy::{
+    primitives::{Address, B256},
+    rpc::types::Log,
+};
+use async_trait::async_trait;
+use diesel::{PgConnection, QueryResult};
+
+use crate::{
+    configs::Config,
+    db::{Database, StorageCache},
+};
+
+use super::{
+    burn::BurnHandler, mint::MintHandler, swap::SwapHandler,
+    sync::SyncHandler, transfer::TransferHandler,
+};
+
+// Handlers may fail with any error. An `RpcError` keeps its retry
+// behaviour, other errors stop the indexer once the retries are exhausted.
+pub type HandlerError = Box<dyn Error + Send + Sync>;
+
+#[async_trait]
+pub trait EventHandler: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn topics(&self) -> Vec<B256>;
+
+    // Contracts whose logs are fetched for the handler. Handlers without
+    // addresses receive the logs of the indexed pairs.
+    fn addresses(&self, _config: &Config) -> Vec<Address> {
+        Vec::new()
+    }
+
+    // Runs for every matching log of the chunk, in block and log order.
+    // Extra rows are written with `StorageCache::write` so they are stored
+    // in the same transaction as the chunk.
+    async fn handle(
+        &self,
+        log: &Log,
+        db: &Database,
+        config: &Config,
+        cache: &mut StorageCache,
+    ) -> Result<(), HandlerError>;
+
+    // Runs in the rollback transaction of a chain reorganization, rows
+    // written for blocks after `last_block` must be removed.
+    fn rollback(
+        &self,
+        _connection: &mut PgConnection,
+        _last_block: i32,
+    ) -> QueryResult<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct HandlerRegistry {
+    handlers: Vec<Arc<dyn EventHandler>>,
+    topics: HashMap<B256, Vec<usize>>,
+}
+
+impl HandlerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The handlers of the v2 pair events.
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::new();
+
+        registry.register(MintHandler);
+        registry.register(BurnHandler);
+        registry.register(SwapHandler);
+        registry.register(SyncHandler);
+        registry.register(TransferHandler);
+
+        registry
+    }
+
+    // Handlers of the same topic run in registration order. Handlers are
+    // identified by their position, names may be shared.
+    pub fn register<H: EventHandler + 'static>(&mut self, handler: H) {
+        let index = self.handlers.len();
+
+        for topic in handler.topics() {
+            self.topics.entry(topic).or_default().push(index);
+        }
+
+        self.handlers.push(Arc::new(handler));
+    }
+
+    pub fn handlers(&self) -> &[Arc<dyn EventHandler>] {
+        &self.handlers
+    }
+
+    // Positions of the handlers of the topic.
+    pub fn handlers_for(&self, topic: &B256) -> &[usize] {
+        match self.topics.get(topic) {
+            Some(indexes) => indexes,
+            None => &[],
+        }
+    }
+
+    // Topics requested from the indexed pairs.
+    pub fn pair_topics(&self, config: &Config) -> Vec<B256> {
+        let mut topics: Vec<B256> = self
+            .handlers
+            .iter()
+            .filter(|handler| handler.addresses(config).is_empty())
+            .flat_map(|handler| handler.topics())
+            .collect();
+
+        topics.sort();
+        topics.dedup();
+
+        topics
+    }
+
+    // Contracts other than the pairs and the topics requested from them.
+    pub fn contracts(
+        &self,
+        config: &Config,
+    ) -> Vec<(Vec<Address>, Vec<B256>)> {
+        self.handlers
+            .iter()
+            .map(|handler| (handler.addresses(config), handler.topics()))
+            .filter(|(addresses, _)| !addresses.is_empty())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NamedHandler(B256);
+
+    #[async_trait]
+    impl EventHandler for NamedHandler {
+        fn name(&self) -> &'static str {
+            "sync"
+        }
+
+        fn topics(&self) -> Vec<B256> {
+            vec![self.0]
+        }
+
+        async fn handle(
+            &self,
+            _log: &Log,
+            _db: &Database,
+            _config: &Config,
+            _cache: &mut StorageCache,
+        ) -> Result<(), HandlerError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn keeps_handlers_sharing_a_name_apart() {
+        let mut registry = HandlerRegistry::with_defaults();
+        let defaults = registry.handlers().len();
+
+        registry.register(NamedHandler(B256::repeat_byte(1)));
+        registry.register(NamedHandler(B256::repeat_byte(2)));
+
+        assert_eq!(registry.handlers().len(), defaults + 2);
+        assert_eq!(
+            registry.handlers_for(&B256::repeat_byte(1)),
+            &[defaults]
+        );
+        assert_eq!(
+            registry.handlers_for(&B256::repeat_byte(2)),
+            &[defaults + 1]
+        );
+        assert!(registry.handlers_for(&B256::ZERO).is_empty());
+    }
+
+    #[test]
+    fn runs_handlers_of_a_topic_in_registration_order() {
+        let mut registry = HandlerRegistry::new();
+
+        registry.register(NamedHandler(B256::repeat_byte(1)));
+        registry.register(NamedHandler(B256::repeat_byte(1)));
+
+        assert_eq!(registry.handlers_for(&B256::repeat_byte(1)), &[0, 1]);
+    }
+}
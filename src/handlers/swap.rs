@@ -1,4 +1,5 @@
-use alloy::{rpc::types::Log, sol, sol_types::SolEvent};
+use alloy::{primitives::B256, rpc::types::Log, sol, sol_types::SolEvent};
+use async_trait::async_trait;
 use bigdecimal::BigDecimal;
 
 use crate::{
@@ -10,9 +11,13 @@ use crate::{
     utils::format::{convert_token_to_decimal, parse_u256, zero_bd},
 };
 
-use super::utils::{
-    get_tracked_volume_usd, update_dex_day_data, update_pair_day_data,
-    update_pair_hour_data, update_token_day_data,
+use super::{
+    registry::{EventHandler, HandlerError},
+    utils::{
+        get_pair_factory, get_tracked_volume_usd, update_dex_day_data,
+        update_pair_day_data, update_pair_hour_data,
+        update_token_day_data,
+    },
 };
 
 sol! {
@@ -32,7 +37,7 @@ pub async fn handle_swap(
     db: &Database,
     config: &Config,
     cache: &mut StorageCache,
-) {
+) -> Result<(), HandlerError> {
     let event = Swap::decode_log(&log.inner, true).unwrap();
     let pair_address = event.address.to_string().to_lowercase();
     let sender_address = event.sender.to_string().to_lowercase();
@@ -64,7 +69,7 @@ pub async fn handle_swap(
         Some(token) => token.to_owned(),
         None => match db.get_token(&token0_address).await {
             Some(token) => token,
-            None => return,
+            None => return Ok(()),
         },
     };
 
@@ -72,7 +77,7 @@ pub async fn handle_swap(
         Some(token) => token.to_owned(),
         None => match db.get_token(&token1_address).await {
             Some(token) => token,
-            None => return,
+            None => return Ok(()),
         },
     };
 
@@ -113,10 +118,9 @@ pub async fn handle_swap(
         amount1_total.clone(),
         &token1,
         &pair,
-        db,
+        &cache.bundle,
         config,
-    )
-    .await;
+    );
 
     let tracked_amount_eth: BigDecimal =
         match cache.bundle.eth_price == zero_bd() {
@@ -143,14 +147,15 @@ pub async fn handle_swap(
     pair.untracked_volume_usd += derived_amount_usd.clone();
     pair.tx_count += 1;
 
-    cache.factory.total_volume_usd += tracked_amount_usd.clone();
-    cache.factory.total_volume_eth =
-        cache.factory.total_volume_eth.clone()
-            + tracked_amount_eth.clone();
+    let factory = get_pair_factory(&pair, cache)?;
+
+    factory.total_volume_usd += tracked_amount_usd.clone();
+    factory.total_volume_eth =
+        factory.total_volume_eth.clone() + tracked_amount_eth.clone();
 
-    cache.factory.untracked_volume_usd += derived_amount_usd.clone();
+    factory.untracked_volume_usd += derived_amount_usd.clone();
 
-    cache.factory.tx_count += 1;
+    factory.tx_count += 1;
 
     let swap_id = format!(
         "{}-{}",
@@ -241,4 +246,36 @@ pub async fn handle_swap(
     cache
         .tokens_day_data
         .insert(token1_day_data.id.clone(), token1_day_data);
+
+    Ok(())
+}
+
+pub struct SwapHandler;
+
+#[async_trait]
+impl EventHandler for SwapHandler {
+    fn name(&self) -> &'static str {
+        "swap"
+    }
+
+    fn topics(&self) -> Vec<B256> {
+        vec![Swap::SIGNATURE_HASH]
+    }
+
+    async fn handle(
+        &self,
+        log: &Log,
+        db: &Database,
+        config: &Config,
+        cache: &mut StorageCache,
+    ) -> Result<(), HandlerError> {
+        handle_swap(
+            log.clone(),
+            cache.block_timestamp(log),
+            db,
+            config,
+            cache,
+        )
+        .await
+    }
 }
@@ -1,13 +1,18 @@
-use alloy::{rpc::types::Log, sol, sol_types::SolEvent};
+use alloy::{primitives::B256, rpc::types::Log, sol, sol_types::SolEvent};
+use async_trait::async_trait;
 
 use crate::{
     configs::Config,
-    db::{Database, StorageCache},
+    db::{models::eth_price::DatabaseEthPrice, Database, StorageCache},
     utils::format::{convert_token_to_decimal, parse_u112, zero_bd},
 };
 
-use super::utils::{
-    find_eth_per_token, get_eth_price_usd, get_tracked_liquidity_usd,
+use super::{
+    registry::{EventHandler, HandlerError},
+    utils::{
+        find_eth_per_token, get_eth_price_usd, get_pair_factory,
+        get_tracked_liquidity_usd,
+    },
 };
 
 sol! {
@@ -19,7 +24,7 @@ pub async fn handle_sync(
     db: &Database,
     config: &Config,
     cache: &mut StorageCache,
-) {
+) -> Result<(), HandlerError> {
     let event = Sync::decode_log(&log.inner, true).unwrap();
 
     let pair_address = event.address.to_string().to_lowercase();
@@ -32,23 +37,28 @@ pub async fn handle_sync(
     let token0_address = pair.token0.to_lowercase();
     let token1_address = pair.token1.to_lowercase();
 
-    let mut token0 = match cache.tokens.get(&token0_address) {
-        Some(token) => token.to_owned(),
-        None => match db.get_token(&token0_address).await {
-            Some(token) => token,
-            None => return,
+    let (token0, token1) = tokio::join!(
+        async {
+            match cache.tokens.get(&token0_address) {
+                Some(token) => Some(token.to_owned()),
+                None => db.get_token(&token0_address).await,
+            }
         },
-    };
+        async {
+            match cache.tokens.get(&token1_address) {
+                Some(token) => Some(token.to_owned()),
+                None => db.get_token(&token1_address).await,
+            }
+        }
+    );
 
-    let mut token1 = match cache.tokens.get(&token1_address) {
-        Some(token) => token.to_owned(),
-        None => match db.get_token(&token1_address).await {
-            Some(token) => token,
-            None => return,
-        },
+    let (mut token0, mut token1) = match (token0, token1) {
+        (Some(token0), Some(token1)) => (token0, token1),
+        _ => return Ok(()),
     };
 
-    cache.factory.total_liquidity_eth -= pair.tracked_reserve_eth.clone();
+    get_pair_factory(&pair, cache)?.total_liquidity_eth -=
+        pair.tracked_reserve_eth.clone();
 
     token0.total_liquidity -= pair.reserve0;
     token1.total_liquidity -= pair.reserve1;
@@ -77,14 +87,37 @@ pub async fn handle_sync(
 
     cache.pairs.insert(pair_address.clone(), pair.clone());
 
-    cache.bundle.eth_price = get_eth_price_usd(db, config, cache).await;
+    let (eth_price, eth_price_sources) =
+        get_eth_price_usd(db, config, cache).await;
+
+    cache.bundle.eth_price = eth_price;
+    cache.bundle.eth_price_sources = eth_price_sources;
+
+    let block_number = log.block_number.unwrap() as i32;
 
-    token0.derived_eth =
+    cache.eth_prices.insert(
+        block_number,
+        DatabaseEthPrice::new(
+            block_number,
+            cache.block_timestamp(&log),
+            cache.bundle.eth_price.clone(),
+        ),
+    );
+
+    let token0_price =
         find_eth_per_token(&token0, db, config, cache).await;
 
-    token1.derived_eth =
+    token0.derived_eth = token0_price.derived_eth;
+    token0.price_route = token0_price.route;
+    token0.price_route_liquidity_eth = token0_price.liquidity_eth;
+
+    let token1_price =
         find_eth_per_token(&token1, db, config, cache).await;
 
+    token1.derived_eth = token1_price.derived_eth;
+    token1.price_route = token1_price.route;
+    token1.price_route_liquidity_eth = token1_price.liquidity_eth;
+
     let mut tracked_liquidity_eth = zero_bd();
 
     if cache.bundle.eth_price != zero_bd() {
@@ -93,11 +126,9 @@ pub async fn handle_sync(
             &token0,
             pair.reserve1.clone(),
             &token1,
-            db,
+            &cache.bundle,
             config,
-        )
-        .await
-            / cache.bundle.eth_price.clone()
+        ) / cache.bundle.eth_price.clone()
     }
 
     pair.tracked_reserve_eth = tracked_liquidity_eth.clone();
@@ -108,11 +139,14 @@ pub async fn handle_sync(
     pair.reserve_usd =
         pair.reserve_eth.clone() * cache.bundle.eth_price.clone();
 
-    cache.factory.total_liquidity_eth += tracked_liquidity_eth;
+    let eth_price = cache.bundle.eth_price.clone();
+
+    let factory = get_pair_factory(&pair, cache)?;
 
-    cache.factory.total_liquidity_usd =
-        cache.factory.total_liquidity_eth.clone()
-            * cache.bundle.eth_price.clone();
+    factory.total_liquidity_eth += tracked_liquidity_eth;
+
+    factory.total_liquidity_usd =
+        factory.total_liquidity_eth.clone() * eth_price;
 
     token0.total_liquidity += pair.reserve0.clone();
     token1.total_liquidity += pair.reserve1.clone();
@@ -120,4 +154,29 @@ pub async fn handle_sync(
     cache.pairs.insert(pair_address, pair);
     cache.tokens.insert(token0_address, token0);
     cache.tokens.insert(token1_address, token1);
+
+    Ok(())
+}
+
+pub struct SyncHandler;
+
+#[async_trait]
+impl EventHandler for SyncHandler {
+    fn name(&self) -> &'static str {
+        "sync"
+    }
+
+    fn topics(&self) -> Vec<B256> {
+        vec![Sync::SIGNATURE_HASH]
+    }
+
+    async fn handle(
+        &self,
+        log: &Log,
+        db: &Database,
+        config: &Config,
+        cache: &mut StorageCache,
+    ) -> Result<(), HandlerError> {
+        handle_sync(log.clone(), db, config, cache).await
+    }
 }
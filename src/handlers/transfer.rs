@@ -1,7 +1,9 @@
-use alloy::{rpc::types::Log, sol, sol_types::SolEvent};
+use alloy::{primitives::B256, rpc::types::Log, sol, sol_types::SolEvent};
+use async_trait::async_trait;
 use bigdecimal::BigDecimal;
 
 use crate::{
+    configs::Config,
     db::{
         models::{
             burn::DatabaseBurn, mint::DatabaseMint,
@@ -12,6 +14,8 @@ use crate::{
     utils::format::{address_zero, convert_token_to_decimal, parse_u256},
 };
 
+use super::registry::{EventHandler, HandlerError};
+
 sol! {
     event Transfer(address indexed from,address indexed to,uint256 value);
 }
@@ -220,3 +224,34 @@ pub async fn handle_transfer(
     cache.pairs.insert(pair_address, pair.clone());
     cache.transactions.insert(transaction.id.clone(), transaction.clone());
 }
+
+pub struct TransferHandler;
+
+#[async_trait]
+impl EventHandler for TransferHandler {
+    fn name(&self) -> &'static str {
+        "transfer"
+    }
+
+    fn topics(&self) -> Vec<B256> {
+        vec![Transfer::SIGNATURE_HASH]
+    }
+
+    async fn handle(
+        &self,
+        log: &Log,
+        db: &Database,
+        _config: &Config,
+        cache: &mut StorageCache,
+    ) -> Result<(), HandlerError> {
+        handle_transfer(
+            log.clone(),
+            cache.block_timestamp(log),
+            db,
+            cache,
+        )
+        .await;
+
+        Ok(())
+    }
+}
@@ -1,11 +1,14 @@
 use crate::{
-    configs::Config,
+    chains::PairSide,
+    configs::{Config, PriceAggregation, PriceSource, PricingMode},
     db::{
         models::{
+            bundle::DatabaseBundle,
             data::{
                 DatabaseDexDayData, DatabasePairDayData,
                 DatabasePairHourData, DatabaseTokenDayData,
             },
+            factory::DatabaseFactory,
             pair::DatabasePair,
             token::DatabaseToken,
         },
@@ -13,46 +16,159 @@ use crate::{
     },
     utils::format::{one_bd, zero_bd},
 };
+
+use super::registry::HandlerError;
 use bigdecimal::BigDecimal;
+use serde_json::{json, Value};
 
 pub async fn get_eth_price_usd(
     db: &Database,
     config: &Config,
     cache: &StorageCache,
-) -> BigDecimal {
-    let usdc_pair: Option<DatabasePair> = match config.chain.usdc_weth_pair
-    {
-        Some(pair) => match cache.pairs.get(pair) {
-            Some(pair) => Some(pair.to_owned()),
-            None => db.get_pair(pair).await,
-        },
-        None => None,
+) -> (BigDecimal, Value) {
+    let minimum_liquidity_eth =
+        BigDecimal::from(config.chain.minimum_stable_liquidity_eth);
+
+    let mut prices: Vec<(BigDecimal, BigDecimal)> = Vec::new();
+    let mut sources = Vec::new();
+
+    for stable_pair in config.chain.stable_pairs {
+        let pair = match cache.pairs.get(stable_pair.pair) {
+            Some(pair) => pair.to_owned(),
+            None => match db.get_pair(stable_pair.pair).await {
+                Some(pair) => pair,
+                None => {
+                    sources.push(json!({
+                        "pair": stable_pair.pair,
+                        "used": false,
+                        "reason": "pair not indexed",
+                    }));
+                    continue;
+                }
+            },
+        };
+
+        let (price, reserve_eth, stable_token) =
+            match stable_pair.stable_side {
+                PairSide::Token0 => {
+                    (pair.token0_price, pair.reserve1, pair.token0)
+                }
+                PairSide::Token1 => {
+                    (pair.token1_price, pair.reserve0, pair.token1)
+                }
+            };
+
+        let reason = match get_cached_token(&stable_token, db, cache).await
+        {
+            Some(token)
+                if token.decimals != stable_pair.stable_decimals =>
+            {
+                Some("stable token decimals mismatch")
+            }
+            _ if reserve_eth <= zero_bd() => Some("no liquidity"),
+            _ if reserve_eth < minimum_liquidity_eth => {
+                Some("below minimum liquidity")
+            }
+            _ => None,
+        };
+
+        sources.push(json!({
+            "pair": stable_pair.pair,
+            "price_usd": price.to_string(),
+            "liquidity_eth": reserve_eth.to_string(),
+            "used": reason.is_none(),
+            "reason": reason,
+        }));
+
+        if reason.is_none() {
+            prices.push((price, reserve_eth));
+        }
+    }
+
+    let dex_price = match config.eth_price_aggregation {
+        PriceAggregation::Mean => weighted_mean(prices),
+        PriceAggregation::Median => weighted_median(prices),
     };
 
-    let usdt_pair: Option<DatabasePair> = match config.chain.usdt_weth_pair
-    {
-        Some(pair) => match cache.pairs.get(pair) {
-            Some(pair) => Some(pair.to_owned()),
-            None => db.get_pair(pair).await,
-        },
-        None => None,
+    let eth_price = match (&cache.oracle_eth_price, config.price_source) {
+        (Some(oracle_price), PriceSource::Oracle) => oracle_price.clone(),
+        (Some(oracle_price), PriceSource::OracleFallback)
+            if dex_price == zero_bd() =>
+        {
+            oracle_price.clone()
+        }
+        _ => dex_price,
     };
 
-    match (usdc_pair, usdt_pair) {
-        (Some(usdc), Some(usdt)) => {
-            let total_liquidity_eth =
-                usdt.reserve0.clone() + usdc.reserve0.clone();
+    if let (Some(oracle), Some(oracle_price)) =
+        (config.chain.native_usd_oracle, &cache.oracle_eth_price)
+    {
+        sources.push(json!({
+            "oracle": oracle,
+            "price_usd": oracle_price.to_string(),
+            "used": eth_price == *oracle_price,
+        }));
+    }
+
+    (eth_price, Value::Array(sources))
+}
 
-            let usdt_weight = usdt.reserve0 / total_liquidity_eth.clone();
+fn weighted_mean(prices: Vec<(BigDecimal, BigDecimal)>) -> BigDecimal {
+    let total_weight = prices
+        .iter()
+        .fold(zero_bd(), |total, (_, weight)| total + weight.clone());
 
-            let usdc_weight = usdc.reserve0 / total_liquidity_eth.clone();
+    if total_weight == zero_bd() {
+        return zero_bd();
+    }
 
-            (usdt.token1_price * usdt_weight)
-                + (usdc.token1_price * usdc_weight)
+    prices
+        .into_iter()
+        .fold(zero_bd(), |total, (price, weight)| total + price * weight)
+        / total_weight
+}
+
+fn weighted_median(
+    mut prices: Vec<(BigDecimal, BigDecimal)>,
+) -> BigDecimal {
+    prices.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let total_weight = prices
+        .iter()
+        .fold(zero_bd(), |total, (_, weight)| total + weight.clone());
+
+    if total_weight == zero_bd() {
+        return zero_bd();
+    }
+
+    let half_weight = total_weight / 2;
+
+    let mut cumulative_weight = zero_bd();
+
+    for (price, weight) in prices {
+        cumulative_weight += weight;
+
+        if cumulative_weight >= half_weight {
+            return price;
+        }
+    }
+
+    zero_bd()
+}
+
+pub struct TokenPrice {
+    pub derived_eth: BigDecimal,
+    pub route: String,
+    pub liquidity_eth: BigDecimal,
+}
+
+impl TokenPrice {
+    fn none() -> Self {
+        Self {
+            derived_eth: zero_bd(),
+            route: String::new(),
+            liquidity_eth: zero_bd(),
         }
-        (Some(usdc), _) => usdc.token1_price,
-        // No pairs
-        _ => zero_bd(),
     }
 }
 
@@ -61,83 +177,305 @@ pub async fn find_eth_per_token(
     db: &Database,
     config: &Config,
     cache: &StorageCache,
-) -> BigDecimal {
+) -> TokenPrice {
     let token_address = token.id.to_lowercase();
 
     if token_address == *config.chain.weth {
-        return one_bd();
+        return TokenPrice { derived_eth: one_bd(), ..TokenPrice::none() };
     }
 
+    let mut routes = Vec::new();
+
     // Loop through a set of whitelisted tokens to check if there is any pair for this token.
     for whitelist_token in config.chain.whitelist_tokens {
         let whitelist_token_address = whitelist_token.to_lowercase();
 
-        let pair = db
-            .get_pair_for_tokens(&token_address, &whitelist_token_address)
-            .await;
+        let route = match find_direct_route(
+            &token_address,
+            &whitelist_token_address,
+            db,
+            config,
+            cache,
+        )
+        .await
+        {
+            Some(route) => route,
+            None => continue,
+        };
 
-        if pair.is_none() {
-            continue;
+        if config.pricing_mode == PricingMode::First {
+            return route;
         }
 
-        if pair.is_none() {
-            continue;
+        routes.push(route);
+    }
+
+    if config.two_hop_pricing
+        && (routes.is_empty()
+            || config.pricing_mode == PricingMode::Weighted)
+    {
+        let two_hop_routes =
+            find_two_hop_routes(&token_address, db, config, cache).await;
+
+        if config.pricing_mode == PricingMode::First {
+            return two_hop_routes
+                .into_iter()
+                .next()
+                .unwrap_or_else(TokenPrice::none);
         }
 
-        let pair = pair.unwrap();
+        routes.extend(two_hop_routes);
+    }
 
-        let pair_token0_address = pair.token0.to_lowercase();
-        let pair_token1_address = pair.token0.to_lowercase();
+    weight_routes(routes)
+}
 
-        if pair_token0_address == token_address
-            && pair.reserve_eth
-                > BigDecimal::from(
-                    config.chain.minimum_liquidity_threshold_eth,
-                )
-        {
-            let token0 = match cache.tokens.get(&pair_token0_address) {
-                Some(token) => token.to_owned(),
-                None => match db.get_token(&pair_token0_address).await {
-                    Some(token) => token,
-                    None => continue,
-                },
-            };
+// Averages the route prices weighted by their tracked ETH liquidity. The
+// deepest route is recorded on the token as the main price source.
+fn weight_routes(mut routes: Vec<TokenPrice>) -> TokenPrice {
+    if routes.len() == 1 {
+        return routes.pop().unwrap();
+    }
+
+    let total_liquidity_eth =
+        routes.iter().fold(zero_bd(), |total, route| {
+            total + route.liquidity_eth.clone()
+        });
 
-            return pair.token0_price * token0.derived_eth;
+    let derived_eth = if total_liquidity_eth == zero_bd() {
+        match routes.first() {
+            Some(route) => route.derived_eth.clone(),
+            None => return TokenPrice::none(),
         }
+    } else {
+        routes.iter().fold(zero_bd(), |total, route| {
+            total + route.derived_eth.clone() * route.liquidity_eth.clone()
+        }) / total_liquidity_eth
+    };
+
+    let deepest_route = routes
+        .into_iter()
+        .reduce(|deepest, route| {
+            if route.liquidity_eth > deepest.liquidity_eth {
+                route
+            } else {
+                deepest
+            }
+        })
+        .unwrap();
 
-        if pair_token1_address == token_address
-            && pair.reserve_eth
-                > BigDecimal::from(
-                    config.chain.minimum_liquidity_threshold_eth,
-                )
+    TokenPrice {
+        derived_eth,
+        route: deepest_route.route,
+        liquidity_eth: deepest_route.liquidity_eth,
+    }
+}
+
+async fn find_direct_route(
+    token_address: &str,
+    whitelist_token_address: &str,
+    db: &Database,
+    config: &Config,
+    cache: &StorageCache,
+) -> Option<TokenPrice> {
+    let rate = get_tokens_rate(
+        token_address,
+        whitelist_token_address,
+        db,
+        config,
+        cache,
+    )
+    .await?;
+
+    let whitelist_token =
+        get_cached_token(whitelist_token_address, db, cache).await?;
+
+    Some(TokenPrice {
+        derived_eth: rate.derived_eth * whitelist_token.derived_eth,
+        ..rate
+    })
+}
+
+async fn find_two_hop_routes(
+    token_address: &str,
+    db: &Database,
+    config: &Config,
+    cache: &StorageCache,
+) -> Vec<TokenPrice> {
+    let mut routes = Vec::new();
+
+    let mut neighbours: Vec<String> = cache
+        .graph_neighbours(token_address)
+        .into_iter()
+        .filter(|neighbour| {
+            !config.chain.whitelist_tokens.contains(&neighbour.as_str())
+        })
+        .collect();
+
+    // Sorted so the first route is the same on every run.
+    neighbours.sort();
+
+    for neighbour in neighbours {
+        // Only neighbours paired with a whitelisted token are priced. The
+        // graph lookups stay in memory, the rates may read the database.
+        let whitelist_tokens: Vec<String> = config
+            .chain
+            .whitelist_tokens
+            .iter()
+            .map(|whitelist_token| whitelist_token.to_lowercase())
+            .filter(|whitelist_token| {
+                !cache.graph_pairs(&neighbour, whitelist_token).is_empty()
+            })
+            .collect();
+
+        if whitelist_tokens.is_empty() {
+            continue;
+        }
+
+        let first_route = match get_tokens_rate(
+            token_address,
+            &neighbour,
+            db,
+            config,
+            cache,
+        )
+        .await
         {
-            let token1 = match cache.tokens.get(&pair_token1_address) {
-                Some(token) => token.to_owned(),
-                None => match db.get_token(&pair_token1_address).await {
-                    Some(token) => token,
-                    None => continue,
-                },
+            Some(route) => route,
+            None => continue,
+        };
+
+        for whitelist_token_address in whitelist_tokens {
+            let second_route = match find_direct_route(
+                &neighbour,
+                &whitelist_token_address,
+                db,
+                config,
+                cache,
+            )
+            .await
+            {
+                Some(route) => route,
+                None => continue,
+            };
+
+            let liquidity_eth = if first_route.liquidity_eth
+                < second_route.liquidity_eth
+            {
+                first_route.liquidity_eth.clone()
+            } else {
+                second_route.liquidity_eth
             };
 
-            return pair.token1_price * token1.derived_eth;
+            routes.push(TokenPrice {
+                derived_eth: first_route.derived_eth.clone()
+                    * second_route.derived_eth,
+                route: format!(
+                    "{}>{}",
+                    first_route.route, second_route.route
+                ),
+                liquidity_eth,
+            });
         }
     }
 
-    zero_bd()
+    routes
+}
+
+// Returns how much of the other token one unit of the token is worth through
+// the pairs between them. Only pairs of the pricing factory are used unless
+// cross-factory pricing is enabled, in which case the rates of the pairs of
+// every factory are weighted by their liquidity.
+async fn get_tokens_rate(
+    token_address: &str,
+    other_token_address: &str,
+    db: &Database,
+    config: &Config,
+    cache: &StorageCache,
+) -> Option<TokenPrice> {
+    let pricing_factory = config.chain.pricing_factory().name;
+
+    let pairs: Vec<String> = cache
+        .graph_pairs(token_address, other_token_address)
+        .iter()
+        .filter(|(factory, _)| {
+            config.cross_factory_pricing || factory == pricing_factory
+        })
+        .map(|(_, pair)| pair.clone())
+        .collect();
+
+    let mut routes = Vec::new();
+
+    for pair_address in pairs {
+        if let Some((rate, liquidity_eth)) =
+            get_pair_rate(&pair_address, token_address, db, config, cache)
+                .await
+        {
+            routes.push(TokenPrice {
+                derived_eth: rate,
+                route: pair_address,
+                liquidity_eth,
+            });
+        }
+    }
+
+    if routes.is_empty() {
+        return None;
+    }
+
+    Some(weight_routes(routes))
 }
 
-pub async fn get_tracked_volume_usd(
+// Returns how much of the other pair token one unit of the token is worth,
+// and the tracked ETH liquidity of the pair, for pairs deep enough to be
+// used for pricing.
+async fn get_pair_rate(
+    pair_address: &str,
+    token_address: &str,
+    db: &Database,
+    config: &Config,
+    cache: &StorageCache,
+) -> Option<(BigDecimal, BigDecimal)> {
+    let pair = match cache.pairs.get(pair_address) {
+        Some(pair) => pair.to_owned(),
+        None => db.get_pair(pair_address).await?,
+    };
+
+    if pair.reserve_eth
+        <= BigDecimal::from(config.chain.minimum_liquidity_threshold_eth)
+    {
+        return None;
+    }
+
+    if pair.token0.to_lowercase() == token_address {
+        Some((pair.token1_price, pair.tracked_reserve_eth))
+    } else {
+        Some((pair.token0_price, pair.tracked_reserve_eth))
+    }
+}
+
+async fn get_cached_token(
+    token_address: &str,
+    db: &Database,
+    cache: &StorageCache,
+) -> Option<DatabaseToken> {
+    match cache.tokens.get(token_address) {
+        Some(token) => Some(token.to_owned()),
+        None => db.get_token(token_address).await,
+    }
+}
+
+// The bundle is the one of the chunk being processed, which may hold an
+// oracle price that is not stored yet.
+pub fn get_tracked_volume_usd(
     token_amount0: BigDecimal,
     token0: &DatabaseToken,
     token_amount1: BigDecimal,
     token1: &DatabaseToken,
     pair: &DatabasePair,
-    db: &Database,
+    bundle: &DatabaseBundle,
     config: &Config,
 ) -> BigDecimal {
-    let bundle = db.get_bundle().await;
-
     let price0: BigDecimal =
         token0.derived_eth.clone() * bundle.eth_price.clone();
     let price1: BigDecimal =
@@ -212,16 +550,14 @@ pub async fn get_tracked_volume_usd(
     zero_bd()
 }
 
-pub async fn get_tracked_liquidity_usd(
+pub fn get_tracked_liquidity_usd(
     token_amount0: BigDecimal,
     token0: &DatabaseToken,
     token_amount1: BigDecimal,
     token1: &DatabaseToken,
-    db: &Database,
+    bundle: &DatabaseBundle,
     config: &Config,
 ) -> BigDecimal {
-    let bundle = db.get_bundle().await;
-
     let price0 = token0.derived_eth.clone() * bundle.eth_price.clone();
     let price1 = token1.derived_eth.clone() * bundle.eth_price.clone();
 
@@ -255,6 +591,21 @@ pub async fn get_tracked_liquidity_usd(
     zero_bd()
 }
 
+// Pairs indexed before several factories were supported are tagged with
+// 'taya_swap', which is missing when the chain names its factory otherwise.
+pub fn get_pair_factory<'a>(
+    pair: &DatabasePair,
+    cache: &'a mut StorageCache,
+) -> Result<&'a mut DatabaseFactory, HandlerError> {
+    cache.factories.get_mut(&pair.factory).ok_or_else(|| {
+        format!(
+            "pair {} belongs to unknown factory {}",
+            pair.id, pair.factory
+        )
+        .into()
+    })
+}
+
 pub async fn update_dex_day_data(
     db: &Database,
     timestamp: i32,
@@ -275,11 +626,18 @@ pub async fn update_dex_day_data(
             },
         };
 
-    factory_day_data.total_liquidity_usd =
-        cache.factory.total_liquidity_usd.clone();
-    factory_day_data.total_liquidity_eth =
-        cache.factory.total_liquidity_eth.clone();
-    factory_day_data.tx_count = cache.factory.tx_count;
+    // Day data covers all the factories of the chain.
+    factory_day_data.total_liquidity_usd = zero_bd();
+    factory_day_data.total_liquidity_eth = zero_bd();
+    factory_day_data.tx_count = 0;
+
+    for factory in cache.factories.values() {
+        factory_day_data.total_liquidity_usd +=
+            factory.total_liquidity_usd.clone();
+        factory_day_data.total_liquidity_eth +=
+            factory.total_liquidity_eth.clone();
+        factory_day_data.tx_count += factory.tx_count;
+    }
 
     cache
         .dex_day_data
@@ -378,7 +736,7 @@ pub async fn update_token_day_data(
     db: &Database,
     cache: &mut StorageCache,
 ) -> DatabaseTokenDayData {
-    let bundle = db.get_bundle().await;
+    let bundle = cache.bundle.clone();
     let day_id = timestamp / 86400;
     let day_start_timestamp = day_id * 86400;
 
@@ -418,3 +776,139 @@ pub async fn update_token_day_data(
 
     token_day_data
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn prices(values: &[(i32, i32)]) -> Vec<(BigDecimal, BigDecimal)> {
+        values
+            .iter()
+            .map(|(price, weight)| {
+                (BigDecimal::from(*price), BigDecimal::from(*weight))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn weighted_mean_of_no_prices_is_zero() {
+        assert_eq!(weighted_mean(Vec::new()), zero_bd());
+        assert_eq!(
+            weighted_mean(prices(&[(3000, 0), (3100, 0)])),
+            zero_bd()
+        );
+    }
+
+    #[test]
+    fn weighted_mean_uses_the_weights() {
+        assert_eq!(
+            weighted_mean(prices(&[(3000, 3), (3100, 1)])),
+            BigDecimal::from(3025)
+        );
+    }
+
+    #[test]
+    fn weighted_median_of_no_prices_is_zero() {
+        assert_eq!(weighted_median(Vec::new()), zero_bd());
+        assert_eq!(
+            weighted_median(prices(&[(3000, 0), (3100, 0)])),
+            zero_bd()
+        );
+    }
+
+    #[test]
+    fn weighted_median_of_a_single_price() {
+        assert_eq!(
+            weighted_median(prices(&[(3000, 5)])),
+            BigDecimal::from(3000)
+        );
+    }
+
+    #[test]
+    fn weighted_median_follows_the_heaviest_side() {
+        assert_eq!(
+            weighted_median(prices(&[(3200, 1), (3000, 1), (3100, 5)])),
+            BigDecimal::from(3100)
+        );
+        assert_eq!(
+            weighted_median(prices(&[(9000, 1), (3000, 10)])),
+            BigDecimal::from(3000)
+        );
+    }
+
+    #[test]
+    fn weighted_median_takes_the_lower_price_at_an_exact_half() {
+        assert_eq!(
+            weighted_median(prices(&[(3100, 2), (3000, 2)])),
+            BigDecimal::from(3000)
+        );
+    }
+
+    fn route(
+        derived_eth: i32,
+        route: &str,
+        liquidity_eth: i32,
+    ) -> TokenPrice {
+        TokenPrice {
+            derived_eth: BigDecimal::from(derived_eth),
+            route: route.to_owned(),
+            liquidity_eth: BigDecimal::from(liquidity_eth),
+        }
+    }
+
+    #[test]
+    fn weight_routes_of_no_routes_is_none() {
+        let price = weight_routes(Vec::new());
+
+        assert_eq!(price.derived_eth, zero_bd());
+        assert_eq!(price.route, "");
+    }
+
+    #[test]
+    fn weight_routes_keeps_a_single_route() {
+        let price = weight_routes(vec![route(2, "0x1", 0)]);
+
+        assert_eq!(price.derived_eth, BigDecimal::from(2));
+        assert_eq!(price.route, "0x1");
+    }
+
+    #[test]
+    fn weight_routes_without_liquidity_takes_the_first_route() {
+        let price =
+            weight_routes(vec![route(2, "0x1", 0), route(4, "0x2", 0)]);
+
+        assert_eq!(price.derived_eth, BigDecimal::from(2));
+        assert_eq!(price.route, "0x1");
+        assert_eq!(price.liquidity_eth, zero_bd());
+    }
+
+    #[test]
+    fn weight_routes_ignores_illiquid_routes() {
+        let price = weight_routes(vec![
+            route(100, "0x1", 0),
+            route(2, "0x2", 10),
+            route(4, "0x3", 10),
+        ]);
+
+        assert_eq!(price.derived_eth, BigDecimal::from(3));
+        assert_eq!(price.route, "0x2");
+        assert_eq!(price.liquidity_eth, BigDecimal::from(10));
+    }
+
+    #[test]
+    fn weight_routes_mixes_direct_and_two_hop_routes() {
+        let price = weight_routes(vec![
+            route(2, "0x1", 10),
+            route(4, "0x2>0x3", 30),
+        ]);
+
+        assert_eq!(
+            price.derived_eth,
+            BigDecimal::from_str("3.5").unwrap()
+        );
+        assert_eq!(price.route, "0x2>0x3");
+        assert_eq!(price.liquidity_eth, BigDecimal::from(30));
+    }
+}
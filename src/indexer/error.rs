@@ -0,0 +1,82 @@
+use std::fmt;
+
+use alloy::transports::TransportError;
+
+use crate::{handlers::registry::HandlerError, rpc::error::RpcError};
+
+#[derive(Debug)]
+pub enum SyncError {
+    Rpc(RpcError),
+    Handler(&'static str, HandlerError),
+    ChainMismatch(u64, u64),
+    ReorgTooDeep(i32),
+    SyncMode(String, String),
+}
+
+impl SyncError {
+    // RPC errors returned by a handler keep their own variant.
+    pub fn handler(name: &'static str, error: HandlerError) -> Self {
+        match error.downcast::<RpcError>() {
+            Ok(error) => SyncError::Rpc(*error),
+            Err(error) => SyncError::Handler(name, error),
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SyncError::Rpc(error) => error.is_retryable(),
+            _ => false,
+        }
+    }
+
+    // Errors that need an operator, the indexer stops instead of syncing
+    // again.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            SyncError::ChainMismatch(_, _)
+                | SyncError::ReorgTooDeep(_)
+                | SyncError::SyncMode(_, _)
+        )
+    }
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Rpc(error) => write!(f, "{}", error),
+            SyncError::Handler(name, error) => {
+                write!(f, "{} handler failed: {}", name, error)
+            }
+            SyncError::ChainMismatch(expected, found) => write!(
+                f,
+                "RPC serves chain {} instead of {}",
+                found, expected
+            ),
+            SyncError::ReorgTooDeep(depth) => write!(
+                f,
+                "chain reorganization deeper than the {} tracked blocks",
+                depth
+            ),
+            SyncError::SyncMode(indexed, requested) => write!(
+                f,
+                "database was indexed with sync mode '{}' but '{}' was requested",
+                indexed, requested
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+impl From<RpcError> for SyncError {
+    fn from(error: RpcError) -> Self {
+        SyncError::Rpc(error)
+    }
+}
+
+impl From<TransportError> for SyncError {
+    fn from(error: TransportError) -> Self {
+        SyncError::Rpc(RpcError::from(error))
+    }
+}
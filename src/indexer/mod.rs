@@ -0,0 +1,673 @@
+pub mod error;
+mod prefetch;
+mod subscription;
+
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use alloy::primitives::Address;
+use log::{debug, info, warn};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    configs::{BlockTag, Config, PriceSource},
+    db::{
+        models::{block::DatabaseBlock, eth_price::DatabaseEthPrice},
+        reorg::find_fork_block,
+        Database, StorageCache,
+    },
+    handlers::{
+        blocks::handle_block_timestamps,
+        events::EventsHandler,
+        pairs::handle_pairs,
+        registry::{EventHandler, HandlerRegistry},
+    },
+    rpc::{error::RpcError, Rpc},
+    utils::format::zero_bd,
+};
+use error::SyncError;
+use prefetch::ChunkLogs;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+const FORK_SEARCH_BLOCKS: usize = 8;
+
+enum ChunkStatus {
+    Synced,
+    Reorganized,
+    Cancelled,
+}
+
+pub struct Indexer {
+    pub config: Config,
+    pub rpc: Arc<Rpc>,
+    pub db: Database,
+    cancel: CancellationToken,
+    handlers: HandlerRegistry,
+    poll_interval: Duration,
+}
+
+pub struct IndexerBuilder {
+    config: Config,
+    rpc: Option<Rpc>,
+    db: Option<Database>,
+    cancel: CancellationToken,
+    handlers: HandlerRegistry,
+    poll_interval: Duration,
+}
+
+impl IndexerBuilder {
+    pub fn rpc(mut self, rpc: Rpc) -> Self {
+        self.rpc = Some(rpc);
+        self
+    }
+
+    pub fn database(mut self, db: Database) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    // Shares a cancellation token, e.g. a child token of a process wide
+    // shutdown token.
+    pub fn cancellation_token(
+        mut self,
+        cancel: CancellationToken,
+    ) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    // Registers a handler next to the built-in pair event handlers.
+    pub fn handler<H: EventHandler + 'static>(
+        mut self,
+        handler: H,
+    ) -> Self {
+        self.handlers.register(handler);
+        self
+    }
+
+    // Connects the RPC endpoints and the database that were not provided.
+    // Fails when the database was indexed with another sync mode.
+    pub async fn build(mut self) -> Result<Indexer, SyncError> {
+        let rpc = match self.rpc {
+            Some(rpc) => rpc,
+            None => Rpc::new(&self.config).await,
+        };
+
+        let rpc = Arc::new(rpc.cancellation_token(self.cancel.clone()));
+
+        let db = match self.db {
+            Some(db) => db,
+            None => Database::new(&self.config).await,
+        };
+
+        let mode = self.config.sync_mode();
+
+        if let Err(indexed_mode) = db.check_sync_mode(&mode).await {
+            return Err(SyncError::SyncMode(indexed_mode, mode));
+        }
+
+        if !self.config.chain.contracts.is_empty() {
+            self.handlers.register(EventsHandler::new(&self.config.chain));
+        }
+
+        Ok(Indexer {
+            config: self.config,
+            rpc,
+            db,
+            cancel: self.cancel,
+            handlers: self.handlers,
+            poll_interval: self.poll_interval,
+        })
+    }
+}
+
+impl Indexer {
+    pub fn builder(config: Config) -> IndexerBuilder {
+        IndexerBuilder {
+            config,
+            rpc: None,
+            db: None,
+            cancel: CancellationToken::new(),
+            handlers: HandlerRegistry::with_defaults(),
+            poll_interval: POLL_INTERVAL,
+        }
+    }
+
+    // Cancelling the handle stops the indexer after the chunk being
+    // processed is stored.
+    pub fn cancellation_handle(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    // Follows the chain until the indexer is cancelled. Failed batches are
+    // synced again on the next poll, only fatal errors stop the indexer.
+    pub async fn run(&self) -> Result<(), SyncError> {
+        info!(target: self.config.chain.name, "Indexing chain {}", self.config.chain.id);
+
+        let result = match &self.config.ws {
+            Some(ws) => self.follow_chain(ws).await,
+            None => self.poll_chain().await,
+        };
+
+        info!(target: self.config.chain.name, "Stopped at block {}", self.last_synced_block().await);
+
+        result
+    }
+
+    async fn poll_chain(&self) -> Result<(), SyncError> {
+        while !self.is_cancelled() {
+            match self.sync_once().await {
+                Ok(_) | Err(SyncError::Rpc(RpcError::Cancelled)) => {}
+                Err(error) if error.is_fatal() => return Err(error),
+                Err(error) => {
+                    warn!(target: self.config.chain.name, "Unable to sync the chain: {}", error);
+                }
+            }
+
+            self.sleep(self.poll_interval).await;
+        }
+
+        Ok(())
+    }
+
+    // Syncs until the given block is indexed or the indexer is cancelled.
+    // Network failures of the chain head request are retried, batch
+    // failures are returned.
+    pub async fn run_until(&self, block: i32) -> Result<(), SyncError> {
+        while !self.is_cancelled() {
+            if self.last_synced_block().await >= block {
+                return Ok(());
+            }
+
+            match self.rpc.get_last_block().await {
+                Ok(last_chain_block) => {
+                    self.sync_blocks(std::cmp::min(
+                        last_chain_block,
+                        block,
+                    ))
+                    .await?;
+
+                    // Only wait for new blocks when the target is not
+                    // reached yet, a reorganization is synced again at once.
+                    if last_chain_block >= block {
+                        continue;
+                    }
+                }
+                Err(RpcError::Cancelled) => {}
+                Err(error) if error.is_retryable() => {
+                    warn!(target: self.config.chain.name, "Unable to fetch the last chain block: {}", error);
+                }
+                Err(error) => return Err(error.into()),
+            }
+
+            self.sleep(self.poll_interval).await;
+        }
+
+        Ok(())
+    }
+
+    // Syncs up to the current chain head once and returns the last indexed
+    // block.
+    pub async fn sync_once(&self) -> Result<i32, SyncError> {
+        let last_chain_block = self.rpc.get_last_block().await?;
+
+        self.sync_blocks(last_chain_block).await?;
+
+        Ok(self.last_synced_block().await)
+    }
+
+    pub async fn last_synced_block(&self) -> i32 {
+        let last_synced_block = self.db.get_last_block_indexed().await;
+
+        std::cmp::max(last_synced_block, self.config.chain.start_block)
+    }
+
+    // Sleeps until the duration elapses or the indexer is cancelled.
+    async fn sleep(&self, duration: Duration) {
+        tokio::select! {
+            _ = self.cancel.cancelled() => {}
+            _ = tokio::time::sleep(duration) => {}
+        }
+    }
+
+    // Returns false when a chain reorganization or a cancellation
+    // interrupted the sync.
+    async fn sync_blocks(
+        &self,
+        last_chain_block: i32,
+    ) -> Result<bool, SyncError> {
+        let config = &self.config;
+
+        let last_synced_block = self.last_synced_block().await;
+
+        info!(target: config.chain.name, "Start sync from block {} to {} with {} blocks each batch",
+            last_synced_block,
+            last_chain_block,
+            self.rpc.blocks_per_chunk(config.batch_size)
+        );
+
+        let reorg_window_start = last_chain_block - config.reorg_depth;
+
+        let known_pairs: Vec<String> = self
+            .db
+            .get_factories()
+            .await
+            .into_values()
+            .flat_map(|factory| factory.pairs.into_iter().flatten())
+            .collect();
+
+        let mut prefetched = self.prefetch_chunks(
+            last_synced_block + 1,
+            last_chain_block,
+            known_pairs,
+            reorg_window_start,
+        );
+
+        while let Some((first_block, last_block, logs)) =
+            prefetched.next().await
+        {
+            let status = self
+                .sync_chunk_with_retries(
+                    first_block,
+                    last_block,
+                    reorg_window_start,
+                    logs,
+                )
+                .await?;
+
+            match status {
+                ChunkStatus::Synced => {}
+                ChunkStatus::Reorganized | ChunkStatus::Cancelled => {
+                    return Ok(false)
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn sync_chunk_with_retries(
+        &self,
+        first_block: i32,
+        last_block: i32,
+        reorg_window_start: i32,
+        logs: Option<ChunkLogs>,
+    ) -> Result<ChunkStatus, SyncError> {
+        let mut attempt = 0;
+        let mut fatal_attempt = 0;
+
+        loop {
+            if self.is_cancelled() {
+                return Ok(ChunkStatus::Cancelled);
+            }
+
+            match self
+                .sync_chunk(
+                    first_block,
+                    last_block,
+                    reorg_window_start,
+                    logs.clone(),
+                )
+                .await
+            {
+                Ok(status) => {
+                    let pool_stats = self.db.pool_stats();
+
+                    debug!(target: self.config.chain.name, "Database pool has {} connections with {} idle",
+                        pool_stats.connections, pool_stats.idle_connections
+                    );
+
+                    return Ok(status);
+                }
+                Err(SyncError::Rpc(RpcError::Cancelled)) => {
+                    return Ok(ChunkStatus::Cancelled)
+                }
+                Err(error) if error.is_fatal() => return Err(error),
+                Err(error) => {
+                    // Network failures are retried until the endpoints
+                    // recover, other errors are unlikely to go away and are
+                    // returned once the batch retries are exhausted.
+                    if !error.is_retryable() {
+                        if fatal_attempt >= self.config.batch_retries {
+                            return Err(error);
+                        }
+
+                        fatal_attempt += 1;
+                    }
+
+                    let delay = self.rpc.backoff_delay(attempt);
+
+                    warn!(target: self.config.chain.name, "Failed to sync blocks {} to {} ({}), retrying in {} ms",
+                        first_block,
+                        last_block,
+                        error,
+                        delay.as_millis()
+                    );
+
+                    self.sleep(delay).await;
+
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    async fn sync_chunk(
+        &self,
+        first_block: i32,
+        last_block: i32,
+        reorg_window_start: i32,
+        logs: Option<ChunkLogs>,
+    ) -> Result<ChunkStatus, SyncError> {
+        let (rpc, db, config) = (&self.rpc, &self.db, &self.config);
+
+        let track_reorgs = config.block_tag != BlockTag::Finalized
+            && last_block > reorg_window_start;
+
+        let mut chunk_block = None;
+
+        if track_reorgs {
+            let first_header = rpc.get_block(first_block).await?;
+
+            if let Some(parent) = db.get_block(first_block - 1).await {
+                if parent.hash != first_header.parent_hash {
+                    self.handle_reorg(parent.number).await?;
+                    return Ok(ChunkStatus::Reorganized);
+                }
+            }
+
+            // The header is fetched before the logs, so a reorg that
+            // happens while the chunk is processed is detected on the
+            // next chunk.
+            chunk_block = if first_block == last_block {
+                Some(first_header)
+            } else {
+                Some(rpc.get_block(last_block).await?)
+            };
+        }
+
+        // A cancellation abandons the chunk before anything is stored, a
+        // chunk being stored is always completed.
+        let mut cache = tokio::select! {
+            _ = self.cancel.cancelled() => {
+                info!(target: config.chain.name, "Abandoned blocks {} to {}", first_block, last_block);
+
+                return Ok(ChunkStatus::Cancelled);
+            }
+            cache = self.handle_chunk(first_block, last_block, chunk_block, logs) => cache?,
+        };
+
+        cache.store().await;
+
+        if track_reorgs {
+            db.prune_journal(reorg_window_start).await;
+        }
+
+        Ok(ChunkStatus::Synced)
+    }
+
+    // Processes the logs of the chunk into a cache that is not stored yet.
+    async fn handle_chunk(
+        &self,
+        first_block: i32,
+        last_block: i32,
+        chunk_block: Option<DatabaseBlock>,
+        logs: Option<ChunkLogs>,
+    ) -> Result<StorageCache, SyncError> {
+        let (rpc, db, config) = (&self.rpc, &self.db, &self.config);
+
+        // Logs fetched ahead of time are only used when they belong to the
+        // same block the reorganization tracking just fetched.
+        let logs = match (logs, &chunk_block) {
+            (Some(logs), Some(block))
+                if logs.hash.as_ref() != Some(&block.hash) =>
+            {
+                None
+            }
+            (logs, _) => logs,
+        };
+
+        let (pair_logs, fetched_pair_logs, blocks) = match logs {
+            Some(logs) => (logs.factory, Some(logs.pairs), logs.blocks),
+            None => (
+                rpc.get_factory_logs_batch(
+                    first_block as u64,
+                    last_block as u64,
+                    config,
+                )
+                .await?,
+                None,
+                Vec::new(),
+            ),
+        };
+
+        let mut cache =
+            StorageCache::new(db, first_block, last_block, chunk_block)
+                .await;
+
+        for block in blocks {
+            cache.blocks.insert(block.number, block);
+        }
+
+        if let Some(oracle) = config.chain.native_usd_oracle {
+            if config.price_source != PriceSource::Dex {
+                // The fallback keeps the DEX price when the oracle can't
+                // be read, only the oracle source depends on it.
+                match rpc.get_oracle_price(oracle, last_block).await {
+                    Ok(oracle_price) => {
+                        if config.price_source == PriceSource::Oracle
+                            || cache.bundle.eth_price == zero_bd()
+                        {
+                            cache.bundle.eth_price = oracle_price.clone();
+
+                            // The price is recorded at the chunk boundary,
+                            // blocks with a Sync event record their own.
+                            let timestamp =
+                                match cache.blocks.get(&last_block) {
+                                    Some(block) => block.timestamp,
+                                    None => {
+                                        rpc.get_block(last_block)
+                                            .await?
+                                            .timestamp
+                                    }
+                                };
+
+                            cache.eth_prices.insert(
+                                last_block,
+                                DatabaseEthPrice::new(
+                                    last_block,
+                                    timestamp,
+                                    oracle_price.clone(),
+                                ),
+                            );
+                        }
+
+                        cache.oracle_eth_price = Some(oracle_price);
+                    }
+                    Err(error)
+                        if config.price_source
+                            == PriceSource::OracleFallback =>
+                    {
+                        warn!(target: config.chain.name, "Unable to read the oracle price at block {} ({}), using the DEX price",
+                            last_block, error
+                        );
+                    }
+                    Err(error) => return Err(error.into()),
+                }
+            }
+        }
+
+        handle_block_timestamps(&pair_logs, db, rpc, &mut cache).await?;
+
+        handle_pairs(pair_logs, db, rpc, &mut cache).await?;
+
+        let pairs: Vec<String> = cache
+            .factories
+            .values()
+            .flat_map(|factory| factory.pairs.iter().flatten().cloned())
+            .collect();
+
+        let known_pairs: HashSet<String> = pairs.iter().cloned().collect();
+
+        let mut event_logs = match fetched_pair_logs {
+            Some(logs) => logs
+                .into_iter()
+                .filter(|log| {
+                    known_pairs.contains(
+                        &log.address().to_string().to_lowercase(),
+                    )
+                })
+                .collect(),
+            None if pairs.is_empty() => Vec::new(),
+            None => {
+                rpc.get_pairs_logs_batch(
+                    &pairs,
+                    &self.handlers.pair_topics(config),
+                    first_block as u64,
+                    last_block as u64,
+                )
+                .await?
+            }
+        };
+
+        // Logs of the contracts registered by handlers are not prefetched.
+        for (addresses, topics) in self.handlers.contracts(config) {
+            event_logs.extend(
+                rpc.get_contract_logs_batch(
+                    addresses,
+                    topics,
+                    first_block as u64,
+                    last_block as u64,
+                )
+                .await?,
+            );
+        }
+
+        if !event_logs.is_empty() {
+            handle_block_timestamps(&event_logs, db, rpc, &mut cache)
+                .await?;
+
+            event_logs.sort_unstable_by_key(|log| {
+                let block_number = log.block_number.unwrap();
+                let log_index = log.log_index.unwrap();
+                (block_number, log_index)
+            });
+
+            event_logs
+                .dedup_by_key(|log| (log.block_number, log.log_index));
+
+            let handlers = self.handlers.handlers();
+
+            let mut counts = vec![0; handlers.len()];
+
+            // Pair handlers only see the logs of the indexed pairs,
+            // contract handlers the logs of their own contracts.
+            let handler_addresses: Vec<HashSet<Address>> = handlers
+                .iter()
+                .map(|handler| {
+                    handler.addresses(config).into_iter().collect()
+                })
+                .collect();
+
+            for log in event_logs {
+                let topic = match log.topic0() {
+                    Some(topic) => topic,
+                    None => continue,
+                };
+
+                for &index in self.handlers.handlers_for(topic) {
+                    let handler = &handlers[index];
+                    let addresses = &handler_addresses[index];
+
+                    let matches = if addresses.is_empty() {
+                        known_pairs.contains(
+                            &log.address().to_string().to_lowercase(),
+                        )
+                    } else {
+                        addresses.contains(&log.address())
+                    };
+
+                    if !matches {
+                        continue;
+                    }
+
+                    handler
+                        .handle(&log, db, config, &mut cache)
+                        .await
+                        .map_err(|error| {
+                            SyncError::handler(handler.name(), error)
+                        })?;
+
+                    counts[index] += 1;
+                }
+            }
+
+            let processed: Vec<String> = handlers
+                .iter()
+                .zip(counts)
+                .map(|(handler, count)| {
+                    format!("{} {}", count, handler.name())
+                })
+                .collect();
+
+            info!(target: config.chain.name, "Processed {} events", processed.join(", "));
+        }
+
+        Ok(cache)
+    }
+
+    async fn handle_reorg(
+        &self,
+        orphaned_block: i32,
+    ) -> Result<(), SyncError> {
+        let config = &self.config;
+
+        warn!(target: config.chain.name, "Chain reorganization detected at block {}", orphaned_block);
+
+        let stored_blocks = self
+            .db
+            .get_blocks(
+                orphaned_block - config.reorg_depth,
+                orphaned_block,
+            )
+            .await;
+
+        // Stored blocks are checked from the newest, a few at a time as the
+        // fork is usually close to the head.
+        for stored_blocks in stored_blocks.chunks(FORK_SEARCH_BLOCKS) {
+            let numbers: Vec<i32> =
+                stored_blocks.iter().map(|block| block.number).collect();
+
+            let canonical_blocks = self.rpc.get_blocks(&numbers).await?;
+
+            if let Some(fork_block) =
+                find_fork_block(stored_blocks, &canonical_blocks)
+            {
+                let last_block = self
+                    .db
+                    .rollback(
+                        fork_block,
+                        self.handlers.handlers().to_vec(),
+                    )
+                    .await;
+
+                info!(target: config.chain.name, "Rolled back to block {} after fork at block {}",
+                    last_block, fork_block
+                );
+
+                return Ok(());
+            }
+        }
+
+        Err(SyncError::ReorgTooDeep(config.reorg_depth))
+    }
+}
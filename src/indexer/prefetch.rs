@@ -0,0 +1,284 @@
+use std::{collections::HashSet, sync::Arc};
+
+use alloy::{primitives::B256, rpc::types::Log, sol_types::SolEvent};
+use futures::{stream, Stream, StreamExt};
+use log::warn;
+use tokio::{sync::mpsc, task::JoinHandle};
+use tokio_util::sync::DropGuard;
+
+use crate::{
+    configs::Config,
+    db::{models::block::DatabaseBlock, Database},
+    handlers::pairs::PairCreated,
+    rpc::{error::RpcError, Rpc},
+};
+
+use super::Indexer;
+
+#[derive(Clone)]
+pub struct ChunkLogs {
+    pub hash: Option<String>,
+    pub factory: Vec<Log>,
+    pub pairs: Vec<Log>,
+    pub blocks: Vec<DatabaseBlock>,
+}
+
+pub type PrefetchedChunk = (i32, i32, Option<ChunkLogs>);
+
+// Chunks handed out in order by the prefetch task. Dropping it stops the
+// task, e.g. after a chain reorganization.
+pub struct Prefetch {
+    receiver: mpsc::Receiver<PrefetchedChunk>,
+    task: JoinHandle<()>,
+    _stop: DropGuard,
+}
+
+impl Prefetch {
+    pub async fn next(&mut self) -> Option<PrefetchedChunk> {
+        match self.receiver.recv().await {
+            Some(chunk) => Some(chunk),
+            None => {
+                // The channel also closes when the task panicked.
+                if let Err(error) = (&mut self.task).await {
+                    if error.is_panic() {
+                        std::panic::resume_unwind(error.into_panic());
+                    }
+                }
+
+                None
+            }
+        }
+    }
+}
+
+struct PrefetchState {
+    next_block: i32,
+    last_block: i32,
+    pairs: Vec<String>,
+    stopped: bool,
+}
+
+// Owned copies of the indexer services, so the downloads run in their own
+// task.
+struct Prefetcher {
+    config: Config,
+    rpc: Arc<Rpc>,
+    db: Database,
+    topics: Vec<B256>,
+}
+
+impl Indexer {
+    // Downloads the logs of the next chunks in a separate task while the
+    // current one is processed. Factory logs are fetched in order because
+    // every chunk needs the pairs created before it, pair logs and block
+    // headers are fetched concurrently and handed out in chunk order.
+    // Chunks inside the reorganization window, or after a failed prefetch,
+    // are fetched when they are processed. Chunks are split as they are
+    // fetched, so they follow the eth_getLogs range of the chain.
+    pub(super) fn prefetch_chunks(
+        &self,
+        first_block: i32,
+        last_block: i32,
+        pairs: Vec<String>,
+        reorg_window_start: i32,
+    ) -> Prefetch {
+        let prefetcher = Prefetcher {
+            config: self.config.clone(),
+            rpc: self.rpc.clone(),
+            db: self.db.clone(),
+            topics: self.handlers.pair_topics(&self.config),
+        };
+
+        let (sender, receiver) =
+            mpsc::channel(std::cmp::max(self.config.prefetch_chunks, 1));
+
+        let stop = self.cancel.child_token();
+
+        let task = tokio::spawn({
+            let stop = stop.clone();
+
+            async move {
+                let chunks = prefetcher.chunks(
+                    first_block,
+                    last_block,
+                    pairs,
+                    reorg_window_start,
+                );
+
+                tokio::pin!(chunks);
+
+                let forward = async {
+                    while let Some(chunk) = chunks.next().await {
+                        if sender.send(chunk).await.is_err() {
+                            return;
+                        }
+                    }
+                };
+
+                tokio::select! {
+                    _ = stop.cancelled() => {}
+                    _ = forward => {}
+                }
+            }
+        });
+
+        Prefetch { receiver, task, _stop: stop.drop_guard() }
+    }
+}
+
+impl Prefetcher {
+    fn chunks(
+        &self,
+        first_block: i32,
+        last_block: i32,
+        pairs: Vec<String>,
+        reorg_window_start: i32,
+    ) -> impl Stream<Item = PrefetchedChunk> + '_ {
+        let config = &self.config;
+
+        let state = PrefetchState {
+            next_block: first_block,
+            last_block,
+            pairs,
+            stopped: config.prefetch_chunks == 0,
+        };
+
+        stream::unfold(state, move |mut state| async move {
+            if state.next_block > state.last_block {
+                return None;
+            }
+
+            let first_block = state.next_block;
+            let last_block = std::cmp::min(
+                first_block + self.rpc.blocks_per_chunk(config.batch_size) - 1,
+                state.last_block,
+            );
+
+            state.next_block = last_block + 1;
+
+            if state.stopped || last_block > reorg_window_start {
+                return Some(((first_block, last_block, None), state));
+            }
+
+            match self
+                .rpc
+                .get_factory_logs_batch(
+                    first_block as u64,
+                    last_block as u64,
+                    config,
+                )
+                .await
+            {
+                Ok(factory_logs) => {
+                    for log in factory_logs.iter() {
+                        let event =
+                            PairCreated::decode_log(&log.inner, true)
+                                .unwrap();
+
+                        state
+                            .pairs
+                            .push(event.pair.to_string().to_lowercase());
+                    }
+
+                    let pairs = state.pairs.clone();
+
+                    Some((
+                        (first_block, last_block, Some((factory_logs, pairs))),
+                        state,
+                    ))
+                }
+                Err(error) => {
+                    if !matches!(error, RpcError::Cancelled) {
+                        warn!(target: config.chain.name, "Unable to prefetch factory logs from block {} ({})",
+                            first_block, error
+                        );
+                    }
+
+                    state.stopped = true;
+
+                    Some(((first_block, last_block, None), state))
+                }
+            }
+        })
+        .map(move |(first_block, last_block, prefetched)| async move {
+            let (factory_logs, pairs) = match prefetched {
+                Some(prefetched) => prefetched,
+                None => return (first_block, last_block, None),
+            };
+
+            match self
+                .prefetch_chunk(first_block, last_block, factory_logs, pairs)
+                .await
+            {
+                Ok(logs) => (first_block, last_block, Some(logs)),
+                Err(RpcError::Cancelled) => (first_block, last_block, None),
+                Err(error) => {
+                    warn!(target: config.chain.name, "Unable to prefetch logs from block {} ({})",
+                        first_block, error
+                    );
+
+                    (first_block, last_block, None)
+                }
+            }
+        })
+        .buffered(std::cmp::max(config.prefetch_chunks, 1))
+    }
+
+    async fn prefetch_chunk(
+        &self,
+        first_block: i32,
+        last_block: i32,
+        factory_logs: Vec<Log>,
+        pairs: Vec<String>,
+    ) -> Result<ChunkLogs, RpcError> {
+        let pair_logs = if pairs.is_empty() {
+            Vec::new()
+        } else {
+            self.rpc
+                .get_pairs_logs_batch(
+                    &pairs,
+                    &self.topics,
+                    first_block as u64,
+                    last_block as u64,
+                )
+                .await?
+        };
+
+        let missing_blocks: HashSet<i32> = factory_logs
+            .iter()
+            .chain(pair_logs.iter())
+            .filter(|log| log.block_timestamp.is_none())
+            .map(|log| log.block_number.unwrap() as i32)
+            .collect();
+
+        let mut blocks = Vec::new();
+
+        if !missing_blocks.is_empty() {
+            blocks = self
+                .db
+                .get_blocks_by_number(
+                    missing_blocks.iter().copied().collect(),
+                )
+                .await;
+
+            let stored_blocks: HashSet<i32> =
+                blocks.iter().map(|block| block.number).collect();
+
+            let missing_blocks: Vec<i32> = missing_blocks
+                .into_iter()
+                .filter(|block_number| {
+                    !stored_blocks.contains(block_number)
+                })
+                .collect();
+
+            blocks.extend(self.rpc.get_blocks(&missing_blocks).await?);
+        }
+
+        Ok(ChunkLogs {
+            hash: None,
+            factory: factory_logs,
+            pairs: pair_logs,
+            blocks,
+        })
+    }
+}
@@ -0,0 +1,335 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use alloy::{
+    primitives::{Address, B256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::{Filter, Header, Log},
+    sol_types::SolEvent,
+};
+use futures::StreamExt;
+use log::{info, warn};
+use tokio::{sync::mpsc, task::JoinSet};
+
+use crate::{handlers::pairs::PairCreated, rpc::error::RpcError};
+
+use super::{error::SyncError, prefetch::ChunkLogs, ChunkStatus, Indexer};
+
+enum Notification {
+    Head(Box<Header>),
+    FactoryLog(Log),
+    PairLog(Log),
+    Closed(&'static str),
+}
+
+impl Indexer {
+    pub(super) async fn follow_chain(
+        &self,
+        ws: &str,
+    ) -> Result<(), SyncError> {
+        let mut attempt = 0;
+
+        while !self.is_cancelled() {
+            if let Err(error) =
+                self.subscribe_chain(ws, &mut attempt).await
+            {
+                if self.is_cancelled() {
+                    return Ok(());
+                }
+
+                if error.is_fatal() {
+                    return Err(error);
+                }
+
+                let delay = self.rpc.backoff_delay(attempt);
+
+                warn!(target: self.config.chain.name, "WebSocket subscription failed ({}), reconnecting in {} ms",
+                    error,
+                    delay.as_millis()
+                );
+
+                self.sleep(delay).await;
+
+                attempt += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn subscribe_chain(
+        &self,
+        ws: &str,
+        attempt: &mut u32,
+    ) -> Result<(), SyncError> {
+        let config = &self.config;
+
+        let client = ProviderBuilder::new().connect(ws).await?;
+
+        let chain_id = client.get_chain_id().await?;
+
+        if chain_id != config.chain.id {
+            return Err(SyncError::ChainMismatch(
+                config.chain.id,
+                chain_id,
+            ));
+        }
+
+        let factory_filter = Filter::new()
+            .address(config.chain.factory_addresses())
+            .event(PairCreated::SIGNATURE);
+
+        // The subscription can't follow a growing address list, so pair
+        // events are subscribed by topic and the logs of other contracts are
+        // dropped as they arrive. Pairs are added when their PairCreated log
+        // arrives, blocks next to one are fetched with eth_getLogs in case
+        // the pair logs arrived first.
+        let pairs_filter = Filter::new()
+            .event_signature(self.handlers.pair_topics(config));
+
+        let known_pairs: HashSet<Address> = self
+            .db
+            .get_factories()
+            .await
+            .into_values()
+            .flat_map(|factory| factory.pairs.into_iter().flatten())
+            .map(|pair| pair.parse().unwrap())
+            .collect();
+
+        let known_pairs = Arc::new(RwLock::new(known_pairs));
+
+        // Logs are subscribed before heads, so the logs of every block
+        // announced after the first head are complete.
+        let factory_logs =
+            client.subscribe_logs(&factory_filter).await?.into_stream();
+        let pairs_logs =
+            client.subscribe_logs(&pairs_filter).await?.into_stream();
+        let heads = client.subscribe_blocks().await?.into_stream();
+
+        info!(target: config.chain.name, "Subscribed to new blocks and logs over WebSocket");
+
+        // Subscription channels drop items when the reader falls behind, so
+        // the streams are drained by their own tasks while blocks are
+        // processed.
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut forwarders = JoinSet::new();
+
+        forwarders.spawn(forward(
+            factory_logs,
+            sender.clone(),
+            "factory logs",
+            {
+                let known_pairs = known_pairs.clone();
+
+                move |log: &Log| {
+                    if let Ok(event) =
+                        PairCreated::decode_log(&log.inner, true)
+                    {
+                        known_pairs.write().unwrap().insert(event.pair);
+                    }
+
+                    true
+                }
+            },
+            Notification::FactoryLog,
+        ));
+        forwarders.spawn(forward(
+            pairs_logs,
+            sender.clone(),
+            "pair logs",
+            move |log: &Log| {
+                known_pairs.read().unwrap().contains(&log.address())
+            },
+            Notification::PairLog,
+        ));
+        forwarders.spawn(forward(
+            heads,
+            sender,
+            "new heads",
+            |_| true,
+            |header| Notification::Head(Box::new(header)),
+        ));
+
+        let mut first_head = None;
+        let mut headers: BTreeMap<i32, (B256, B256)> = BTreeMap::new();
+        let mut factory_buffer: BTreeMap<i32, Vec<Log>> = BTreeMap::new();
+        let mut pairs_buffer: BTreeMap<i32, Vec<Log>> = BTreeMap::new();
+
+        loop {
+            let notification = tokio::select! {
+                _ = self.cancel.cancelled() => return Ok(()),
+                notification = receiver.recv() => match notification {
+                    Some(notification) => notification,
+                    None => return Ok(()),
+                },
+            };
+
+            match notification {
+                Notification::FactoryLog(log) => {
+                    let block_number = log.block_number.unwrap() as i32;
+                    factory_buffer
+                        .entry(block_number)
+                        .or_default()
+                        .push(log);
+                }
+                Notification::PairLog(log) => {
+                    let block_number = log.block_number.unwrap() as i32;
+                    pairs_buffer
+                        .entry(block_number)
+                        .or_default()
+                        .push(log);
+                }
+                Notification::Head(header) => {
+                    *attempt = 0;
+
+                    // Heads come from the WebSocket, so the HTTP endpoints
+                    // are not polled for the last block in this mode.
+                    self.rpc.check_lag_if_due().await;
+
+                    let head = header.number as i32;
+
+                    headers
+                        .insert(head, (header.hash, header.parent_hash));
+
+                    let first_head = *first_head.get_or_insert(head);
+
+                    self.sync_head(
+                        head,
+                        first_head,
+                        &headers,
+                        &factory_buffer,
+                        &pairs_buffer,
+                    )
+                    .await?;
+
+                    let last_synced_block = self.last_synced_block().await;
+
+                    // Buffered data is kept for the reorganization window in
+                    // case the blocks are rolled back and processed again.
+                    let keep_from = last_synced_block - config.reorg_depth;
+
+                    headers = headers.split_off(&keep_from);
+                    factory_buffer = factory_buffer.split_off(&keep_from);
+                    pairs_buffer = pairs_buffer.split_off(&keep_from);
+                }
+                Notification::Closed(subscription) => {
+                    return Err(RpcError::Other(format!(
+                        "{} subscription closed",
+                        subscription
+                    ))
+                    .into());
+                }
+            }
+        }
+    }
+
+    // A block is processed once the next head is announced, so all of its
+    // logs have been received. Blocks before the subscription started, next
+    // to a new pair, or whose buffered logs don't match the canonical chain,
+    // are fetched with eth_getLogs instead.
+    async fn sync_head(
+        &self,
+        head: i32,
+        first_head: i32,
+        headers: &BTreeMap<i32, (B256, B256)>,
+        factory_buffer: &BTreeMap<i32, Vec<Log>>,
+        pairs_buffer: &BTreeMap<i32, Vec<Log>>,
+    ) -> Result<(), SyncError> {
+        let config = &self.config;
+
+        let target_block = head - 1 - config.confirmations;
+
+        let gap_end = std::cmp::min(target_block, first_head);
+
+        if self.last_synced_block().await < gap_end
+            && !self.sync_blocks(gap_end).await?
+        {
+            return Ok(());
+        }
+
+        let reorg_window_start = head - config.reorg_depth;
+
+        let first_block = self.last_synced_block().await + 1;
+
+        for block_number in first_block..=target_block {
+            let logs = match (
+                headers.get(&block_number),
+                headers.get(&(block_number + 1)),
+            ) {
+                (Some((hash, _)), Some((_, child_parent_hash)))
+                    if block_number > first_head
+                        && hash == child_parent_hash
+                        && !factory_buffer.contains_key(&block_number)
+                        && !factory_buffer
+                            .contains_key(&(block_number - 1)) =>
+                {
+                    let canonical = |logs: Option<&Vec<Log>>| -> Vec<Log> {
+                        logs.map(|logs| {
+                            logs.iter()
+                                .filter(|log| {
+                                    !log.removed
+                                        && log.block_hash == Some(*hash)
+                                })
+                                .cloned()
+                                .collect()
+                        })
+                        .unwrap_or_default()
+                    };
+
+                    Some(ChunkLogs {
+                        hash: Some(hash.to_string()),
+                        factory: canonical(
+                            factory_buffer.get(&block_number),
+                        ),
+                        pairs: canonical(pairs_buffer.get(&block_number)),
+                        blocks: Vec::new(),
+                    })
+                }
+                _ => None,
+            };
+
+            let status = self
+                .sync_chunk_with_retries(
+                    block_number,
+                    block_number,
+                    reorg_window_start,
+                    logs,
+                )
+                .await?;
+
+            match status {
+                ChunkStatus::Synced => {}
+                ChunkStatus::Reorganized | ChunkStatus::Cancelled => {
+                    return Ok(())
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn forward<T, S, F>(
+    mut stream: S,
+    sender: mpsc::UnboundedSender<Notification>,
+    subscription: &'static str,
+    keep: F,
+    notification: fn(T) -> Notification,
+) where
+    S: futures::Stream<Item = T> + Unpin,
+    F: Fn(&T) -> bool,
+{
+    while let Some(item) = stream.next().await {
+        if !keep(&item) {
+            continue;
+        }
+
+        if sender.send(notification(item)).is_err() {
+            return;
+        }
+    }
+
+    let _ = sender.send(Notification::Closed(subscription));
+}
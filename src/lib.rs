@@ -3,5 +3,6 @@ pub mod chains;
 pub mod configs;
 pub mod db;
 pub mod handlers;
+pub mod indexer;
 pub mod rpc;
 pub mod utils;
@@ -0,0 +1,234 @@
+use std::{
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use alloy::providers::DynProvider;
+
+const FAILURE_PENALTY_MS: u64 = 1_000;
+
+// Failures are halved for every interval without a new one, so a penalized
+// endpoint is tried again once it scores better than the others.
+const FAILURE_DECAY_MS: u64 = 30_000;
+
+pub struct Endpoint {
+    pub client: DynProvider,
+    pub url: String,
+    created: Instant,
+    excluded: AtomicBool,
+    failures: AtomicU32,
+    head: AtomicU64,
+    last_failure_ms: AtomicU64,
+    lagging: AtomicBool,
+    latency_ms: AtomicU64,
+    verified: AtomicBool,
+}
+
+impl Endpoint {
+    pub fn new(url: String, client: DynProvider) -> Self {
+        Self {
+            client,
+            url,
+            created: Instant::now(),
+            excluded: AtomicBool::new(false),
+            failures: AtomicU32::new(0),
+            head: AtomicU64::new(0),
+            last_failure_ms: AtomicU64::new(0),
+            lagging: AtomicBool::new(false),
+            latency_ms: AtomicU64::new(0),
+            verified: AtomicBool::new(false),
+        }
+    }
+
+    // Lower is healthier. The score is the average response time plus a
+    // penalty for every recent failure, lagging endpoints are only used
+    // when nothing else is available.
+    pub fn score(&self) -> u64 {
+        self.score_at(self.elapsed_ms())
+    }
+
+    fn score_at(&self, now_ms: u64) -> u64 {
+        let penalty = self.failures_at(now_ms) as u64 * FAILURE_PENALTY_MS;
+
+        let score = self.latency_ms.load(Ordering::Relaxed) + penalty;
+
+        if self.is_lagging() {
+            score.saturating_add(u64::MAX / 2)
+        } else {
+            score
+        }
+    }
+
+    pub fn record_success(&self, latency: Duration) {
+        let sample = latency.as_millis() as u64;
+        let average = self.latency_ms.load(Ordering::Relaxed);
+
+        let latency_ms =
+            if average == 0 { sample } else { (average * 7 + sample) / 8 };
+
+        self.latency_ms.store(latency_ms, Ordering::Relaxed);
+
+        let failures = self.failures_at(self.elapsed_ms());
+        self.failures.store(failures.saturating_sub(1), Ordering::Relaxed);
+    }
+
+    pub fn record_failure(&self) {
+        self.record_failure_at(self.elapsed_ms());
+    }
+
+    fn record_failure_at(&self, now_ms: u64) {
+        let failures = self.failures_at(now_ms);
+
+        self.failures.store(failures.saturating_add(1), Ordering::Relaxed);
+        self.last_failure_ms.store(now_ms, Ordering::Relaxed);
+    }
+
+    fn failures_at(&self, now_ms: u64) -> u32 {
+        let since_failure = now_ms
+            .saturating_sub(self.last_failure_ms.load(Ordering::Relaxed));
+
+        let halvings = since_failure / FAILURE_DECAY_MS;
+
+        self.failures
+            .load(Ordering::Relaxed)
+            .checked_shr(halvings.min(32) as u32)
+            .unwrap_or(0)
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.created.elapsed().as_millis() as u64
+    }
+
+    pub fn head(&self) -> u64 {
+        self.head.load(Ordering::Relaxed)
+    }
+
+    pub fn set_head(&self, head: u64) {
+        self.head.store(head, Ordering::Relaxed);
+    }
+
+    // The chain id of an endpoint is checked before its first request.
+    pub fn is_verified(&self) -> bool {
+        self.verified.load(Ordering::Relaxed)
+    }
+
+    pub fn set_verified(&self) {
+        self.verified.store(true, Ordering::Relaxed);
+    }
+
+    // Excluded endpoints serve another chain and are never used.
+    pub fn is_excluded(&self) -> bool {
+        self.excluded.load(Ordering::Relaxed)
+    }
+
+    pub fn set_excluded(&self) {
+        self.excluded.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_lagging(&self) -> bool {
+        self.lagging.load(Ordering::Relaxed)
+    }
+
+    pub fn set_lagging(&self, lagging: bool) {
+        self.lagging.store(lagging, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::providers::ProviderBuilder;
+
+    use super::*;
+
+    fn endpoint(latency_ms: u64) -> Endpoint {
+        let url = "http://127.0.0.1:8545";
+
+        let client = DynProvider::new(
+            ProviderBuilder::new().on_http(url.parse().unwrap()),
+        );
+
+        let endpoint = Endpoint::new(url.to_owned(), client);
+
+        endpoint.record_success(Duration::from_millis(latency_ms));
+
+        endpoint
+    }
+
+    #[test]
+    fn scores_latency_and_failures() {
+        let endpoint = endpoint(200);
+
+        assert_eq!(endpoint.score_at(0), 200);
+
+        endpoint.record_failure_at(0);
+        endpoint.record_failure_at(0);
+
+        assert_eq!(endpoint.score_at(0), 2_200);
+    }
+
+    #[test]
+    fn successes_remove_failures() {
+        let endpoint = endpoint(200);
+
+        endpoint.record_failure();
+        endpoint.record_success(Duration::from_millis(200));
+
+        assert_eq!(endpoint.score(), 200);
+    }
+
+    #[test]
+    fn failures_decay_over_time() {
+        let endpoint = endpoint(200);
+
+        for _ in 0..4 {
+            endpoint.record_failure_at(1_000);
+        }
+
+        assert_eq!(endpoint.score_at(1_000), 4_200);
+        assert_eq!(endpoint.score_at(1_000 + FAILURE_DECAY_MS), 2_200);
+        assert_eq!(endpoint.score_at(1_000 + 2 * FAILURE_DECAY_MS), 1_200);
+        assert_eq!(endpoint.score_at(1_000 + 3 * FAILURE_DECAY_MS), 200);
+        assert_eq!(endpoint.score_at(u64::MAX), 200);
+    }
+
+    #[test]
+    fn failures_after_decay_start_from_the_decayed_count() {
+        let endpoint = endpoint(200);
+
+        for _ in 0..4 {
+            endpoint.record_failure_at(0);
+        }
+
+        endpoint.record_failure_at(2 * FAILURE_DECAY_MS);
+
+        assert_eq!(endpoint.score_at(2 * FAILURE_DECAY_MS), 2_200);
+    }
+
+    #[test]
+    fn penalized_endpoint_is_selected_again_after_decay() {
+        let fast = endpoint(100);
+        let slow = endpoint(900);
+
+        fast.record_failure_at(0);
+
+        assert!(fast.score_at(0) > slow.score_at(0));
+        assert!(
+            fast.score_at(FAILURE_DECAY_MS)
+                < slow.score_at(FAILURE_DECAY_MS)
+        );
+    }
+
+    #[test]
+    fn lagging_endpoints_score_last() {
+        let lagging = endpoint(100);
+        let failing = endpoint(100);
+
+        lagging.set_lagging(true);
+
+        for _ in 0..10 {
+            failing.record_failure_at(0);
+        }
+
+        assert!(lagging.score_at(0) > failing.score_at(0));
+    }
+}
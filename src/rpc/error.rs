@@ -0,0 +1,284 @@
+use std::fmt;
+
+use alloy::{
+    contract,
+    providers::MulticallError,
+    transports::{RpcError as TransportRpcError, TransportError},
+};
+
+// Phrases providers use when an eth_getLogs request spans too many blocks
+// or results.
+const RANGE_TOO_LARGE_MESSAGES: [&str; 7] = [
+    "query returned more than",
+    "range too large",
+    "range is too large",
+    "range is too wide",
+    "exceed maximum block range",
+    "response size exceeded",
+    "too many results",
+];
+
+#[derive(Debug, Clone)]
+pub enum RpcError {
+    RateLimited(String),
+    Timeout(String),
+    RangeTooLarge(String),
+    Decode(String),
+    Reverted(String),
+    Unavailable(String),
+    Cancelled,
+    Other(String),
+}
+
+impl RpcError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            RpcError::RateLimited(_)
+                | RpcError::Timeout(_)
+                | RpcError::Other(_)
+        )
+    }
+
+    // Only eth_getLogs errors can mean the requested range is too large.
+    pub fn from_logs_error(error: TransportError) -> Self {
+        let message = error.to_string().to_lowercase();
+
+        if RANGE_TOO_LARGE_MESSAGES
+            .iter()
+            .any(|pattern| message.contains(pattern))
+        {
+            return RpcError::RangeTooLarge(error.to_string());
+        }
+
+        RpcError::from(error)
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::RateLimited(message) => {
+                write!(f, "rate limited: {}", message)
+            }
+            RpcError::Timeout(message) => {
+                write!(f, "timeout: {}", message)
+            }
+            RpcError::RangeTooLarge(message) => {
+                write!(f, "range too large: {}", message)
+            }
+            RpcError::Decode(message) => {
+                write!(f, "decode failure: {}", message)
+            }
+            RpcError::Reverted(message) => {
+                write!(f, "call reverted: {}", message)
+            }
+            RpcError::Unavailable(message) => {
+                write!(f, "no RPC endpoint available: {}", message)
+            }
+            RpcError::Cancelled => write!(f, "request cancelled"),
+            RpcError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<TransportError> for RpcError {
+    fn from(error: TransportError) -> Self {
+        let message = error.to_string();
+        let lowercase_message = message.to_lowercase();
+
+        if matches!(
+            error,
+            TransportRpcError::SerError(_)
+                | TransportRpcError::DeserError { .. }
+        ) {
+            return RpcError::Decode(message);
+        }
+
+        if let TransportRpcError::ErrorResp(payload) = &error {
+            if payload.message.to_lowercase().contains("revert") {
+                return RpcError::Reverted(message);
+            }
+        }
+
+        let rate_limited = match &error {
+            TransportRpcError::ErrorResp(payload) => {
+                payload.is_retry_err()
+            }
+            TransportRpcError::Transport(kind) => kind.is_retry_err(),
+            _ => false,
+        };
+
+        if rate_limited
+            || lowercase_message.contains("rate limit")
+            || lowercase_message.contains("request limit")
+            || lowercase_message.contains("too many requests")
+        {
+            return RpcError::RateLimited(message);
+        }
+
+        if lowercase_message.contains("timeout")
+            || lowercase_message.contains("timed out")
+        {
+            return RpcError::Timeout(message);
+        }
+
+        RpcError::Other(message)
+    }
+}
+
+impl From<contract::Error> for RpcError {
+    fn from(error: contract::Error) -> Self {
+        match error {
+            contract::Error::TransportError(error) => {
+                RpcError::from(error)
+            }
+            contract::Error::AbiError(_)
+            | contract::Error::ZeroData(_, _) => {
+                RpcError::Decode(error.to_string())
+            }
+            _ => RpcError::Other(error.to_string()),
+        }
+    }
+}
+
+impl From<MulticallError> for RpcError {
+    fn from(error: MulticallError) -> Self {
+        match error {
+            MulticallError::TransportError(error) => RpcError::from(error),
+            MulticallError::DecodeError(_)
+            | MulticallError::NoReturnData => {
+                RpcError::Decode(error.to_string())
+            }
+            MulticallError::CallFailed(_) => {
+                RpcError::Reverted(error.to_string())
+            }
+            _ => RpcError::Other(error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::transports::TransportErrorKind;
+    use serde_json::json;
+
+    use super::*;
+
+    fn error_response(code: i64, message: &str) -> TransportError {
+        TransportRpcError::ErrorResp(
+            serde_json::from_value(
+                json!({ "code": code, "message": message }),
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn maps_range_messages_of_get_logs() {
+        for message in [
+            "query returned more than 10000 results",
+            "block range is too wide",
+            "Log response size exceeded.",
+            "exceed maximum block range: 5000",
+        ] {
+            assert!(
+                matches!(
+                    RpcError::from_logs_error(error_response(
+                        -32005, message
+                    )),
+                    RpcError::RangeTooLarge(_)
+                ),
+                "{}",
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_range_messages_of_other_requests() {
+        let error = RpcError::from(error_response(
+            -32000,
+            "query returned more than 10000 results",
+        ));
+
+        assert!(matches!(error, RpcError::Other(_)));
+        assert!(error.is_retryable());
+    }
+
+    #[test]
+    fn maps_unrelated_get_logs_errors() {
+        assert!(matches!(
+            RpcError::from_logs_error(error_response(-32000, "too large")),
+            RpcError::Other(_)
+        ));
+        assert!(matches!(
+            RpcError::from_logs_error(error_response(429, "busy")),
+            RpcError::RateLimited(_)
+        ));
+    }
+
+    #[test]
+    fn maps_rate_limits() {
+        for error in [
+            error_response(429, "busy"),
+            error_response(-32000, "Rate limit exceeded"),
+            error_response(-32000, "daily request limit reached"),
+            TransportErrorKind::custom_str("429 Too Many Requests"),
+        ] {
+            let error = RpcError::from(error);
+
+            assert!(
+                matches!(error, RpcError::RateLimited(_)),
+                "{}",
+                error
+            );
+            assert!(error.is_retryable());
+        }
+    }
+
+    #[test]
+    fn maps_timeouts() {
+        let error = RpcError::from(TransportErrorKind::custom_str(
+            "request timed out",
+        ));
+
+        assert!(matches!(error, RpcError::Timeout(_)));
+        assert!(error.is_retryable());
+    }
+
+    #[test]
+    fn maps_reverts() {
+        let error =
+            RpcError::from(error_response(3, "execution reverted"));
+
+        assert!(matches!(error, RpcError::Reverted(_)));
+        assert!(!error.is_retryable());
+        assert!(error.to_string().starts_with("call reverted: "));
+    }
+
+    #[test]
+    fn maps_decode_failures() {
+        let parse_error = serde_json::from_str::<u64>("0x").unwrap_err();
+
+        let error = RpcError::from(TransportRpcError::deser_err(
+            parse_error,
+            "0x",
+        ));
+
+        assert!(matches!(error, RpcError::Decode(_)));
+        assert!(!error.is_retryable());
+    }
+
+    #[test]
+    fn only_retries_network_errors() {
+        assert!(
+            RpcError::Other("header not found".to_owned()).is_retryable()
+        );
+        assert!(!RpcError::RangeTooLarge(String::new()).is_retryable());
+        assert!(!RpcError::Unavailable(String::new()).is_retryable());
+        assert!(!RpcError::Cancelled.is_retryable());
+    }
+}
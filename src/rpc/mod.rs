@@ -1,186 +1,1098 @@
-use std::str::FromStr;
+pub mod endpoint;
+pub mod error;
+
+use std::{
+    collections::HashSet,
+    future::Future,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
 
 use bigdecimal::BigDecimal;
-use log::info;
+use futures::future::{join_all, try_join_all};
+use log::{debug, error, info, warn};
+use rand::Rng;
+use tokio_util::sync::CancellationToken;
 
 use crate::{
-    abi::{erc20::ERC20, factory::FACTORY},
-    chains::Chain,
-    configs::Config,
-    handlers::{
-        burn::Burn, mint::Mint, pairs::PairCreated, swap::Swap,
-        sync::Sync, transfer::Transfer,
-    },
-    utils::format::parse_u256,
+    abi::{aggregator::AGGREGATOR, erc20::ERC20, factory::FACTORY},
+    chains::{Chain, Factory},
+    configs::{BlockTag, Config},
+    db::models::block::DatabaseBlock,
+    handlers::pairs::PairCreated,
+    utils::format::{convert_token_to_decimal, parse_u256},
 };
 use alloy::{
-    eips::BlockNumberOrTag,
-    primitives::Address,
+    eips::{BlockId, BlockNumberOrTag},
+    primitives::{Address, Bytes, B256, U256},
     providers::{DynProvider, Provider, ProviderBuilder},
-    rpc::types::{Filter, Log},
-    sol_types::SolEvent,
+    rpc::{
+        client::BatchRequest,
+        types::{Block, Filter, Log, TransactionRequest},
+    },
+    sol_types::{sol_data, SolCall, SolEvent, SolType},
+    transports::http::reqwest::Url,
 };
+use endpoint::Endpoint;
+use error::RpcError;
+
+const MAX_BACKOFF_MS: u64 = 30_000;
+
+const LAG_CHECK_INTERVAL_MS: u64 = 10_000;
+
+const SMALL_LOGS_RESPONSE: usize = 1_000;
+
+const MAX_FILTER_SHARDS: usize = 8;
+
+const BLOCK_HEADERS_BATCH_SIZE: usize = 50;
+
+const DEFAULT_TOKEN_DECIMALS: i32 = 18;
+
+// Pair events are filtered by address while the list fits in a few
+// requests, past that it is cheaper to filter by topic and drop the logs of
+// unknown contracts locally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairFilter {
+    Addresses,
+    Sharded,
+    Topics,
+}
 
 pub struct Rpc {
+    pub backoff_ms: u64,
+    pub block_tag: BlockTag,
+    cancel: CancellationToken,
     pub chain: Chain,
-    pub client: DynProvider,
+    pub confirmations: i32,
+    pub endpoints: Vec<Endpoint>,
+    pub logs_range: AtomicU64,
+    pub max_lag: u64,
+    pub retries: u32,
+    next_lag_check_ms: AtomicU64,
+    started: Instant,
 }
 
 impl Rpc {
     pub async fn new(config: &Config) -> Self {
-        info!("Starting rpc service");
+        info!(target: config.chain.name, "Starting rpc service");
 
-        let client = ProviderBuilder::new()
-            .connect(&config.rpc.clone())
-            .await
-            .unwrap();
+        let mut endpoints = Vec::new();
 
-        let dyn_client = DynProvider::new(client.clone());
+        // HTTP clients are built without a request, so an endpoint that is
+        // down at startup is kept and used once it recovers.
+        for url in config.rpc.iter() {
+            let client = match url.parse() {
+                Ok(url) if is_http(&url) => {
+                    DynProvider::new(ProviderBuilder::new().on_http(url))
+                }
+                _ => match ProviderBuilder::new().connect(url).await {
+                    Ok(client) => DynProvider::new(client),
+                    Err(error) => {
+                        warn!(target: config.chain.name, "Unable to connect to RPC {}: {}", url, error);
+                        continue;
+                    }
+                },
+            };
 
-        let client_id = client.get_chain_id().await;
+            endpoints.push(Endpoint::new(url.to_owned(), client));
+        }
 
-        match client_id {
-            Ok(value) => {
-                if value != config.chain.id {
-                    panic!("RPC chain id is invalid");
-                }
+        if endpoints.is_empty() {
+            panic!("no RPC endpoint available");
+        }
+
+        let rpc = Self {
+            backoff_ms: config.rpc_backoff_ms,
+            block_tag: config.block_tag,
+            cancel: CancellationToken::new(),
+            chain: config.chain.clone(),
+            confirmations: config.confirmations,
+            endpoints,
+            logs_range: AtomicU64::new(config.chain.initial_logs_range),
+            max_lag: config.rpc_max_lag,
+            retries: config.rpc_retries,
+            next_lag_check_ms: AtomicU64::new(0),
+            started: Instant::now(),
+        };
+
+        // Endpoints failing the check are penalized and verified again
+        // before their first request.
+        join_all(rpc.endpoints.iter().map(|endpoint| async {
+            if let Err(error) = rpc.verify_endpoint(endpoint).await {
+                endpoint.record_failure();
+
+                warn!(target: rpc.chain.name, "Unable to request eth_chainId from {}: {}",
+                    endpoint.url, error
+                );
             }
-            Err(_) => panic!("unable to request eth_chainId"),
+        }))
+        .await;
+
+        info!(target: config.chain.name, "Using {} RPC endpoints", rpc.usable_endpoints().count());
+
+        rpc
+    }
+
+    // Shares the cancellation token of the indexer using the service.
+    pub fn cancellation_token(
+        mut self,
+        cancel: CancellationToken,
+    ) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    // Chunks span at least the batch size and grow with the eth_getLogs
+    // range, so a chunk is fetched with as few requests as possible.
+    pub fn blocks_per_chunk(&self, batch_size: usize) -> i32 {
+        std::cmp::max(
+            batch_size as u64,
+            self.logs_range.load(Ordering::Relaxed),
+        ) as i32
+    }
+
+    pub fn backoff_delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .backoff_ms
+            .saturating_mul(2_u64.saturating_pow(attempt))
+            .min(MAX_BACKOFF_MS);
+
+        // Jitter over the upper half of the delay keeps retries from
+        // several requests hitting the endpoint at the same time.
+        let jittered = rand::thread_rng().gen_range(delay / 2..=delay);
+
+        Duration::from_millis(jittered)
+    }
+
+    fn usable_endpoints(&self) -> impl Iterator<Item = &Endpoint> {
+        self.endpoints.iter().filter(|endpoint| !endpoint.is_excluded())
+    }
+
+    fn best_endpoint(&self) -> Option<&Endpoint> {
+        self.usable_endpoints().min_by_key(|endpoint| endpoint.score())
+    }
+
+    // Returns false when the endpoint serves another chain, the endpoint is
+    // then excluded.
+    async fn verify_endpoint(
+        &self,
+        endpoint: &Endpoint,
+    ) -> Result<bool, RpcError> {
+        if endpoint.is_verified() {
+            return Ok(true);
         }
 
-        Self { chain: config.chain.clone(), client: dyn_client }
+        let chain_id = endpoint.client.get_chain_id().await?;
+
+        if chain_id != self.chain.id {
+            error!(target: self.chain.name, "RPC {} serves chain {} instead of {}, excluding it",
+                endpoint.url, chain_id, self.chain.id
+            );
+
+            endpoint.set_excluded();
+
+            return Ok(false);
+        }
+
+        endpoint.set_verified();
+
+        Ok(true)
     }
 
-    pub async fn get_last_block(&self) -> Option<i32> {
-        match self.client.get_block_number().await {
-            Ok(block) => Some(block as i32),
-            Err(_) => None,
+    // Only one caller at a time runs the lag check once the interval
+    // elapsed.
+    pub async fn check_lag_if_due(&self) {
+        if self.max_lag == 0 {
+            return;
+        }
+
+        let now = self.started.elapsed().as_millis() as u64;
+        let next_check = self.next_lag_check_ms.load(Ordering::Relaxed);
+
+        if now < next_check {
+            return;
+        }
+
+        if self
+            .next_lag_check_ms
+            .compare_exchange(
+                next_check,
+                now + LAG_CHECK_INTERVAL_MS,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+        {
+            self.check_endpoints_lag().await;
         }
     }
 
-    pub async fn get_factory_logs_batch(
+    // A cancellation interrupts the request and its backoff delays.
+    async fn with_retries<T, F, Fut>(
         &self,
-        first_block: u64,
-        last_block: u64,
-        config: &Config,
-    ) -> Option<Vec<Log>> {
-        let mut all_logs = Vec::new();
+        request: F,
+    ) -> Result<T, RpcError>
+    where
+        F: Fn(DynProvider) -> Fut,
+        Fut: Future<Output = Result<T, RpcError>>,
+    {
+        tokio::select! {
+            _ = self.cancel.cancelled() => Err(RpcError::Cancelled),
+            result = self.request_with_retries(request) => result,
+        }
+    }
 
-        let rpc_block_limit = 100;
+    // Requests go to the healthiest endpoint. A failure penalizes the
+    // endpoint so the retry fails over to another one, the backoff delay is
+    // only applied when no better endpoint is left.
+    async fn request_with_retries<T, F, Fut>(
+        &self,
+        request: F,
+    ) -> Result<T, RpcError>
+    where
+        F: Fn(DynProvider) -> Fut,
+        Fut: Future<Output = Result<T, RpcError>>,
+    {
+        let mut attempt = 0;
+        let mut backoff_attempt = 0;
 
-        let mut current_block = first_block;
-        while current_block <= last_block {
-            let end_block = std::cmp::min(
-                current_block + rpc_block_limit - 1,
-                last_block,
-            );
+        self.check_lag_if_due().await;
 
-            let filter = Filter::new()
-                .from_block(BlockNumberOrTag::Number(current_block))
-                .to_block(BlockNumberOrTag::Number(end_block))
-                .address(config.chain.factory.parse::<Address>().unwrap())
-                .event(PairCreated::SIGNATURE);
+        loop {
+            let endpoint = match self.best_endpoint() {
+                Some(endpoint) => endpoint,
+                None => {
+                    return Err(RpcError::Unavailable(format!(
+                        "no endpoint serves chain {}",
+                        self.chain.id
+                    )))
+                }
+            };
 
-            let batch_logs = self.client.get_logs(&filter).await.unwrap();
+            let start = Instant::now();
 
-            all_logs.extend(batch_logs);
+            let result = match self.verify_endpoint(endpoint).await {
+                Ok(true) => request(endpoint.client.clone()).await,
+                Ok(false) => continue,
+                Err(error) => Err(error),
+            };
 
-            current_block = end_block + 1;
+            match result {
+                Ok(value) => {
+                    endpoint.record_success(start.elapsed());
+                    return Ok(value);
+                }
+                Err(error)
+                    if error.is_retryable() && attempt < self.retries =>
+                {
+                    endpoint.record_failure();
+
+                    attempt += 1;
+
+                    if self
+                        .best_endpoint()
+                        .is_some_and(|best| std::ptr::eq(best, endpoint))
+                    {
+                        let delay = self.backoff_delay(backoff_attempt);
+
+                        warn!(target: self.chain.name, "RPC request to {} failed ({}), retrying in {} ms",
+                            endpoint.url,
+                            error,
+                            delay.as_millis()
+                        );
+
+                        tokio::time::sleep(delay).await;
+
+                        backoff_attempt += 1;
+                    } else {
+                        warn!(target: self.chain.name, "RPC request to {} failed ({}), failing over",
+                            endpoint.url, error
+                        );
+                    }
+                }
+                Err(error) => {
+                    if error.is_retryable() {
+                        endpoint.record_failure();
+                    }
+
+                    return Err(error);
+                }
+            }
         }
+    }
+
+    // Compares the block number reported by every endpoint and flags the
+    // ones trailing the highest head by more than the allowed lag.
+    async fn check_endpoints_lag(&self) {
+        let heads =
+            join_all(self.usable_endpoints().map(|endpoint| async {
+                let start = Instant::now();
+
+                match endpoint.client.get_block_number().await {
+                    Ok(head) => {
+                        endpoint.record_success(start.elapsed());
+                        endpoint.set_head(head);
+                    }
+                    Err(error) => {
+                        endpoint.record_failure();
+                        warn!(target: self.chain.name, "Unable to request eth_blockNumber from {}: {}",
+                        endpoint.url, error
+                    );
+                    }
+                }
+            }));
+
+        heads.await;
 
-        Some(all_logs)
+        let highest_head = self
+            .usable_endpoints()
+            .map(|endpoint| endpoint.head())
+            .max()
+            .unwrap_or(0);
+
+        for endpoint in self.usable_endpoints() {
+            let lagging = endpoint.head() + self.max_lag < highest_head;
+
+            if lagging && !endpoint.is_lagging() {
+                warn!(target: self.chain.name, "RPC {} is {} blocks behind",
+                    endpoint.url,
+                    highest_head - endpoint.head()
+                );
+            }
+
+            endpoint.set_lagging(lagging);
+        }
+    }
+
+    pub async fn get_last_block(&self) -> Result<i32, RpcError> {
+        let tag = match self.block_tag {
+            BlockTag::Latest => {
+                let block = self
+                    .with_retries(|client| async move {
+                        Ok(client.get_block_number().await?)
+                    })
+                    .await?;
+
+                return Ok(block as i32 - self.confirmations);
+            }
+            BlockTag::Safe => BlockNumberOrTag::Safe,
+            BlockTag::Finalized => BlockNumberOrTag::Finalized,
+        };
+
+        self.with_retries(|client| async move {
+            match client.get_block_by_number(tag).await? {
+                Some(block) => Ok(block.header.number as i32),
+                None => Err(RpcError::Other(format!(
+                    "block tag {} not available",
+                    tag
+                ))),
+            }
+        })
+        .await
+    }
+
+    pub async fn get_block(
+        &self,
+        number: i32,
+    ) -> Result<DatabaseBlock, RpcError> {
+        self.with_retries(|client| async move {
+            match client
+                .get_block_by_number(BlockNumberOrTag::Number(
+                    number as u64,
+                ))
+                .await?
+            {
+                Some(block) => Ok(DatabaseBlock::new(
+                    number,
+                    block.header.hash.to_string(),
+                    block.header.parent_hash.to_string(),
+                    block.header.timestamp as i32,
+                )),
+                None => Err(RpcError::Other(format!(
+                    "block {} not found",
+                    number
+                ))),
+            }
+        })
+        .await
+    }
+
+    // Headers are requested in JSON-RPC batches, a batch is retried as a
+    // whole when one of its calls fails.
+    pub async fn get_blocks(
+        &self,
+        numbers: &[i32],
+    ) -> Result<Vec<DatabaseBlock>, RpcError> {
+        let mut blocks = Vec::new();
+
+        for batch in numbers.chunks(BLOCK_HEADERS_BATCH_SIZE) {
+            let headers = self
+                .with_retries(|client| async move {
+                    let mut request = BatchRequest::new(client.client());
+
+                    let calls = batch
+                        .iter()
+                        .map(|number| {
+                            request.add_call::<_, Option<Block>>(
+                                "eth_getBlockByNumber",
+                                &(
+                                    BlockNumberOrTag::Number(
+                                        *number as u64,
+                                    ),
+                                    false,
+                                ),
+                            )
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    request.send().await?;
+
+                    let mut headers = Vec::new();
+
+                    for (number, block) in
+                        batch.iter().zip(try_join_all(calls).await?)
+                    {
+                        match block {
+                            Some(block) => {
+                                headers.push(DatabaseBlock::new(
+                                    *number,
+                                    block.header.hash.to_string(),
+                                    block.header.parent_hash.to_string(),
+                                    block.header.timestamp as i32,
+                                ))
+                            }
+                            None => {
+                                return Err(RpcError::Other(format!(
+                                    "block {} not found",
+                                    number
+                                )))
+                            }
+                        }
+                    }
+
+                    Ok(headers)
+                })
+                .await?;
+
+            blocks.extend(headers);
+        }
+
+        Ok(blocks)
+    }
+
+    pub async fn get_factory_logs_batch(
+        &self,
+        first_block: u64,
+        last_block: u64,
+        config: &Config,
+    ) -> Result<Vec<Log>, RpcError> {
+        let filter = Filter::new()
+            .address(config.chain.factory_addresses())
+            .event(PairCreated::SIGNATURE);
+
+        self.get_logs_batch(filter, first_block, last_block).await
+    }
+
+    pub fn pair_filter_strategy(&self, pair_count: usize) -> PairFilter {
+        if pair_count <= self.chain.max_filter_addresses {
+            PairFilter::Addresses
+        } else if pair_count
+            <= self.chain.max_filter_addresses * MAX_FILTER_SHARDS
+        {
+            PairFilter::Sharded
+        } else {
+            PairFilter::Topics
+        }
     }
 
     pub async fn get_pairs_logs_batch(
         &self,
         pairs: &[String],
+        topics: &[B256],
         first_block: u64,
         last_block: u64,
-    ) -> Option<Vec<Log>> {
+    ) -> Result<Vec<Log>, RpcError> {
         let address_pairs: Vec<Address> = pairs
             .iter()
             .map(|pair| Address::from_str(pair).unwrap())
             .collect();
 
+        let filter = Filter::new().event_signature(topics.to_vec());
+
+        let strategy = self.pair_filter_strategy(address_pairs.len());
+
+        debug!(target: self.chain.name, "Fetching logs for {} pairs with {:?} filter",
+            address_pairs.len(),
+            strategy
+        );
+
+        match strategy {
+            PairFilter::Addresses => {
+                self.get_logs_batch(
+                    filter.address(address_pairs),
+                    first_block,
+                    last_block,
+                )
+                .await
+            }
+            PairFilter::Sharded => {
+                let mut all_logs = Vec::new();
+
+                for shard in
+                    address_pairs.chunks(self.chain.max_filter_addresses)
+                {
+                    let logs = self
+                        .get_logs_batch(
+                            filter.clone().address(shard.to_vec()),
+                            first_block,
+                            last_block,
+                        )
+                        .await?;
+
+                    all_logs.extend(logs);
+                }
+
+                Ok(all_logs)
+            }
+            PairFilter::Topics => {
+                let known_pairs: HashSet<Address> =
+                    address_pairs.into_iter().collect();
+
+                let logs = self
+                    .get_logs_batch(filter, first_block, last_block)
+                    .await?;
+
+                Ok(logs
+                    .into_iter()
+                    .filter(|log| known_pairs.contains(&log.address()))
+                    .collect())
+            }
+        }
+    }
+
+    pub async fn get_contract_logs_batch(
+        &self,
+        addresses: Vec<Address>,
+        topics: Vec<B256>,
+        first_block: u64,
+        last_block: u64,
+    ) -> Result<Vec<Log>, RpcError> {
+        let filter =
+            Filter::new().address(addresses).event_signature(topics);
+
+        self.get_logs_batch(filter, first_block, last_block).await
+    }
+
+    async fn get_logs_batch(
+        &self,
+        filter: Filter,
+        first_block: u64,
+        last_block: u64,
+    ) -> Result<Vec<Log>, RpcError> {
         let mut all_logs = Vec::new();
-        let rpc_block_limit = 100;
 
         let mut current_block = first_block;
         while current_block <= last_block {
-            let end_block = std::cmp::min(
-                current_block + rpc_block_limit - 1,
-                last_block,
-            );
+            let range = self.logs_range.load(Ordering::Relaxed);
+
+            let end_block =
+                std::cmp::min(current_block + range - 1, last_block);
 
-            let filter = Filter::new()
+            let filter = filter
+                .clone()
                 .from_block(BlockNumberOrTag::Number(current_block))
-                .to_block(BlockNumberOrTag::Number(end_block))
-                .address(address_pairs.clone())
-                .events(vec![
-                    Mint::SIGNATURE,
-                    Burn::SIGNATURE,
-                    Swap::SIGNATURE,
-                    Sync::SIGNATURE,
-                    Transfer::SIGNATURE,
-                ]);
-
-            let batch_logs = self.client.get_logs(&filter).await.unwrap();
+                .to_block(BlockNumberOrTag::Number(end_block));
+
+            let filter = &filter;
+
+            let batch_logs = match self
+                .with_retries(move |client| async move {
+                    client
+                        .get_logs(filter)
+                        .await
+                        .map_err(RpcError::from_logs_error)
+                })
+                .await
+            {
+                Ok(logs) => logs,
+                Err(RpcError::RangeTooLarge(message)) => {
+                    let reduced_range = match shrink_logs_range(
+                        range,
+                        self.chain.min_logs_range,
+                    ) {
+                        Some(reduced_range) => reduced_range,
+                        None => {
+                            return Err(RpcError::RangeTooLarge(message))
+                        }
+                    };
+
+                    self.logs_range
+                        .store(reduced_range, Ordering::Relaxed);
+
+                    debug!(target: self.chain.name, "Reduced logs range from {} to {} blocks",
+                        range, reduced_range
+                    );
+
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+
+            if let Some(increased_range) = grow_logs_range(
+                range,
+                end_block - current_block + 1,
+                batch_logs.len(),
+                self.chain.max_logs_range,
+            ) {
+                self.logs_range.store(increased_range, Ordering::Relaxed);
+
+                debug!(target: self.chain.name, "Increased logs range from {} to {} blocks",
+                    range, increased_range
+                );
+            }
 
             all_logs.extend(batch_logs);
 
             current_block = end_block + 1;
         }
 
-        Some(all_logs)
+        Ok(all_logs)
     }
 
     pub async fn get_token_information(
         &self,
         token: String,
-    ) -> (String, String, BigDecimal, i32) {
+    ) -> Result<(String, String, BigDecimal, i32), RpcError> {
         let token_address = Address::from_str(&token).unwrap();
-        let token = ERC20::new(token_address, &self.client);
 
-        let multicall = self
-            .client
-            .multicall()
-            .add(token.name())
-            .add(token.symbol())
-            .add(token.totalSupply())
-            .add(token.decimals());
+        let information = self
+            .with_retries(|client| async move {
+                let token = ERC20::new(token_address, &client);
 
-        let (name, symbol, total_supply, decimals) =
-            multicall.aggregate().await.unwrap();
+                Ok(client
+                    .multicall()
+                    .add(token.name())
+                    .add(token.symbol())
+                    .add(token.totalSupply())
+                    .add(token.decimals())
+                    .aggregate()
+                    .await?)
+            })
+            .await;
 
-        (
-            name._0,
-            symbol._0,
-            parse_u256(total_supply._0),
-            decimals._0 as i32,
-        )
+        match information {
+            Ok((name, symbol, total_supply, decimals)) => Ok((
+                strip_nul(name._0),
+                strip_nul(symbol._0),
+                parse_u256(total_supply._0),
+                decimals._0 as i32,
+            )),
+            Err(RpcError::Decode(_)) | Err(RpcError::Reverted(_)) => {
+                self.get_token_information_fallback(token_address).await
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    // Tokens with non-standard metadata (bytes32 name or symbol, missing
+    // or reverting functions) are read one call at a time and get default
+    // values for what can't be decoded.
+    async fn get_token_information_fallback(
+        &self,
+        token: Address,
+    ) -> Result<(String, String, BigDecimal, i32), RpcError> {
+        let (name, symbol, total_supply, decimals) = tokio::try_join!(
+            self.call_token(token, ERC20::nameCall {}.abi_encode()),
+            self.call_token(token, ERC20::symbolCall {}.abi_encode()),
+            self.call_token(token, ERC20::totalSupplyCall {}.abi_encode()),
+            self.call_token(token, ERC20::decimalsCall {}.abi_encode()),
+        )?;
+
+        warn!(target: self.chain.name, "Token {} has non-standard metadata", token);
+
+        let total_supply = total_supply
+            .and_then(|data| {
+                <sol_data::Uint<256> as SolType>::abi_decode(&data, false)
+                    .ok()
+            })
+            .unwrap_or(U256::ZERO);
+
+        Ok((
+            decode_token_text(name.as_deref()),
+            decode_token_text(symbol.as_deref()),
+            parse_u256(total_supply),
+            decode_token_decimals(decimals.as_deref()),
+        ))
+    }
+
+    // Returns None when the call reverts.
+    async fn call_token(
+        &self,
+        token: Address,
+        data: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, RpcError> {
+        let data = Bytes::from(data);
+
+        self.with_retries(|client| {
+            let request = TransactionRequest::default()
+                .to(token)
+                .input(data.clone().into());
+
+            async move {
+                match client.call(request).await {
+                    Ok(output) => Ok(Some(output.to_vec())),
+                    Err(error) => match RpcError::from(error) {
+                        RpcError::Reverted(_) => Ok(None),
+                        error => Err(error),
+                    },
+                }
+            }
+        })
+        .await
     }
 
     pub async fn get_pair_for_tokens(
         &self,
         token0: String,
         token1: String,
-        config: &Config,
-    ) -> String {
-        let factory = FACTORY::new(
-            config.chain.factory.parse::<Address>().unwrap(),
-            &self.client,
+        factory: &Factory,
+    ) -> Result<String, RpcError> {
+        let factory_address = factory.address.parse::<Address>().unwrap();
+
+        let token0 = Address::from_str(&token0).unwrap();
+        let token1 = Address::from_str(&token1).unwrap();
+
+        let pair = self
+            .with_retries(|client| async move {
+                let factory = FACTORY::new(factory_address, &client);
+
+                Ok(factory.getPair(token0, token1).call().await?)
+            })
+            .await?;
+
+        Ok(pair._0.to_string().to_lowercase())
+    }
+
+    pub async fn get_oracle_price(
+        &self,
+        oracle: &str,
+        block: i32,
+    ) -> Result<BigDecimal, RpcError> {
+        let oracle_address = Address::from_str(oracle).unwrap();
+
+        let (round, decimals, timestamp) = self
+            .with_retries(|client| async move {
+                let aggregator = AGGREGATOR::new(oracle_address, &client);
+
+                Ok(client
+                    .multicall()
+                    .add(aggregator.latestRoundData())
+                    .add(aggregator.decimals())
+                    .get_current_block_timestamp()
+                    .block(BlockId::number(block as u64))
+                    .aggregate()
+                    .await?)
+            })
+            .await?;
+
+        if !round.answer.is_positive() {
+            return Err(RpcError::Decode(format!(
+                "oracle {} returned a non-positive answer {}",
+                oracle, round.answer
+            )));
+        }
+
+        let updated_at: u64 = round.updatedAt.saturating_to();
+        let timestamp: u64 = timestamp.timestamp.saturating_to();
+
+        if timestamp.saturating_sub(updated_at)
+            > self.chain.native_usd_oracle_max_age
+        {
+            return Err(RpcError::Decode(format!(
+                "oracle {} answer updated at {} is stale at block {}",
+                oracle, updated_at, block
+            )));
+        }
+
+        let answer =
+            BigDecimal::from_str(&round.answer.to_string()).unwrap();
+
+        Ok(convert_token_to_decimal(&answer, decimals._0 as i32))
+    }
+}
+
+// Postgres text can't hold NUL characters, bytes32 values are padded with
+// them.
+// Halves the range after a range too large error, None when the minimum
+// range is already used.
+fn shrink_logs_range(range: u64, min_range: u64) -> Option<u64> {
+    if range <= min_range {
+        return None;
+    }
+
+    Some(std::cmp::max(range / 2, min_range))
+}
+
+// Doubles the range after a small response. Only a request that used the
+// full range says something about the provider limits, shorter tail
+// requests are ignored.
+fn grow_logs_range(
+    range: u64,
+    blocks: u64,
+    logs: usize,
+    max_range: u64,
+) -> Option<u64> {
+    if blocks < range || logs >= SMALL_LOGS_RESPONSE || range >= max_range
+    {
+        return None;
+    }
+
+    Some(std::cmp::min(range * 2, max_range))
+}
+
+fn strip_nul(text: String) -> String {
+    text.replace('\0', "")
+}
+
+// Decodes a string return value, or a bytes32 one as used by some early
+// tokens. Anything else is stored as an empty string.
+fn decode_token_text(data: Option<&[u8]>) -> String {
+    let data = match data {
+        Some(data) => data,
+        None => return String::new(),
+    };
+
+    if let Ok(text) = <sol_data::String as SolType>::abi_decode(data, true)
+    {
+        return strip_nul(text);
+    }
+
+    if data.len() == 32 {
+        return strip_nul(String::from_utf8_lossy(data).into_owned());
+    }
+
+    String::new()
+}
+
+fn decode_token_decimals(data: Option<&[u8]>) -> i32 {
+    data.and_then(|data| {
+        <sol_data::Uint<256> as SolType>::abi_decode(data, false).ok()
+    })
+    .and_then(|decimals| u8::try_from(decimals).ok())
+    .map(|decimals| decimals as i32)
+    .filter(|decimals| *decimals <= 77)
+    .unwrap_or(DEFAULT_TOKEN_DECIMALS)
+}
+
+fn is_http(url: &Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chains::CHAINS;
+
+    use super::*;
+
+    fn rpc(backoff_ms: u64) -> Rpc {
+        Rpc {
+            backoff_ms,
+            block_tag: BlockTag::Latest,
+            cancel: CancellationToken::new(),
+            chain: CHAINS[0].clone(),
+            confirmations: 0,
+            endpoints: Vec::new(),
+            logs_range: AtomicU64::new(CHAINS[0].initial_logs_range),
+            max_lag: 0,
+            retries: 3,
+            next_lag_check_ms: AtomicU64::new(0),
+            started: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn backoff_delay_doubles_with_jitter() {
+        let rpc = rpc(100);
+
+        for attempt in 0..4 {
+            let delay = rpc.backoff_delay(attempt).as_millis() as u64;
+            let upper = 100 * 2_u64.pow(attempt);
+
+            assert!((upper / 2..=upper).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[test]
+    fn backoff_delay_is_capped() {
+        let rpc = rpc(100);
+
+        for attempt in [9, 64, u32::MAX] {
+            let delay = rpc.backoff_delay(attempt).as_millis() as u64;
+
+            assert!(
+                (MAX_BACKOFF_MS / 2..=MAX_BACKOFF_MS).contains(&delay),
+                "{}",
+                delay
+            );
+        }
+    }
+
+    fn endpoint(url: &str, latency_ms: u64) -> Endpoint {
+        let client = DynProvider::new(
+            ProviderBuilder::new().on_http(url.parse().unwrap()),
         );
 
-        factory
-            .getPair(
-                Address::from_str(&token0).unwrap(),
-                Address::from_str(&token1).unwrap(),
-            )
-            .call()
-            .await
-            .unwrap()
-            ._0
-            .to_string()
-            .to_lowercase()
+        let endpoint = Endpoint::new(url.to_owned(), client);
+
+        endpoint.record_success(Duration::from_millis(latency_ms));
+
+        endpoint
+    }
+
+    fn best_url(rpc: &Rpc) -> Option<&str> {
+        rpc.best_endpoint().map(|endpoint| endpoint.url.as_str())
+    }
+
+    #[test]
+    fn selects_endpoints_by_score() {
+        let mut rpc = rpc(100);
+
+        rpc.endpoints = vec![
+            endpoint("http://slow", 400),
+            endpoint("http://fast", 100),
+            endpoint("http://lagging", 50),
+            endpoint("http://excluded", 10),
+        ];
+
+        rpc.endpoints[2].set_lagging(true);
+        rpc.endpoints[3].set_excluded();
+
+        assert_eq!(best_url(&rpc), Some("http://fast"));
+
+        rpc.endpoints[1].record_failure();
+
+        assert_eq!(best_url(&rpc), Some("http://slow"));
+
+        rpc.endpoints[0].record_failure();
+        rpc.endpoints[0].record_failure();
+
+        assert_eq!(best_url(&rpc), Some("http://fast"));
+
+        rpc.endpoints[0].set_excluded();
+        rpc.endpoints[1].set_excluded();
+
+        assert_eq!(best_url(&rpc), Some("http://lagging"));
+
+        rpc.endpoints[2].set_excluded();
+
+        assert_eq!(best_url(&rpc), None);
+    }
+
+    #[test]
+    fn shrinks_the_logs_range_down_to_the_minimum() {
+        assert_eq!(shrink_logs_range(1000, 10), Some(500));
+        assert_eq!(shrink_logs_range(15, 10), Some(10));
+        assert_eq!(shrink_logs_range(10, 10), None);
+    }
+
+    #[test]
+    fn grows_the_logs_range_after_small_full_responses() {
+        assert_eq!(grow_logs_range(100, 100, 0, 1000), Some(200));
+        assert_eq!(grow_logs_range(600, 600, 999, 1000), Some(1000));
+    }
+
+    #[test]
+    fn keeps_the_logs_range_without_evidence() {
+        // A tail request shorter than the range.
+        assert_eq!(grow_logs_range(100, 50, 0, 1000), None);
+        // A dense response.
+        assert_eq!(
+            grow_logs_range(100, 100, SMALL_LOGS_RESPONSE, 1000),
+            None
+        );
+        // The maximum range.
+        assert_eq!(grow_logs_range(1000, 1000, 0, 1000), None);
+    }
+
+    #[test]
+    fn chunks_follow_the_logs_range() {
+        let rpc = rpc(100);
+
+        rpc.logs_range.store(20, Ordering::Relaxed);
+        assert_eq!(rpc.blocks_per_chunk(50), 50);
+
+        rpc.logs_range.store(2000, Ordering::Relaxed);
+        assert_eq!(rpc.blocks_per_chunk(50), 2000);
+    }
+
+    #[test]
+    fn pair_filter_strategy_follows_the_pair_count() {
+        let rpc = rpc(100);
+        let limit = rpc.chain.max_filter_addresses;
+
+        assert_eq!(rpc.pair_filter_strategy(1), PairFilter::Addresses);
+        assert_eq!(rpc.pair_filter_strategy(limit), PairFilter::Addresses);
+        assert_eq!(
+            rpc.pair_filter_strategy(limit + 1),
+            PairFilter::Sharded
+        );
+        assert_eq!(
+            rpc.pair_filter_strategy(limit * MAX_FILTER_SHARDS),
+            PairFilter::Sharded
+        );
+        assert_eq!(
+            rpc.pair_filter_strategy(limit * MAX_FILTER_SHARDS + 1),
+            PairFilter::Topics
+        );
+    }
+
+    #[test]
+    fn decodes_string_token_text() {
+        let data =
+            <sol_data::String as SolType>::abi_encode("Wrapped\0 Ether");
+
+        assert_eq!(decode_token_text(Some(&data)), "Wrapped Ether");
+    }
+
+    #[test]
+    fn decodes_bytes32_token_text() {
+        let mut data = [0_u8; 32];
+        data[..3].copy_from_slice(b"MKR");
+
+        assert_eq!(decode_token_text(Some(&data)), "MKR");
+    }
+
+    #[test]
+    fn defaults_missing_token_text() {
+        assert_eq!(decode_token_text(None), "");
+        assert_eq!(decode_token_text(Some(&[1, 2, 3])), "");
+    }
+
+    #[test]
+    fn decodes_token_decimals() {
+        let data =
+            <sol_data::Uint<256> as SolType>::abi_encode(&U256::from(6));
+
+        assert_eq!(decode_token_decimals(Some(&data)), 6);
+    }
+
+    #[test]
+    fn defaults_invalid_token_decimals() {
+        for decimals in [U256::from(78), U256::MAX] {
+            let data =
+                <sol_data::Uint<256> as SolType>::abi_encode(&decimals);
+
+            assert_eq!(
+                decode_token_decimals(Some(&data)),
+                DEFAULT_TOKEN_DECIMALS
+            );
+        }
+
+        assert_eq!(decode_token_decimals(None), DEFAULT_TOKEN_DECIMALS);
+        assert_eq!(
+            decode_token_decimals(Some(&[])),
+            DEFAULT_TOKEN_DECIMALS
+        );
     }
 }